@@ -1,14 +1,33 @@
+pub mod environment;
+mod film;
 mod hittable;
+pub mod integrator;
+mod light;
 mod ray;
+mod sampler;
+mod tile;
 mod util;
 mod vector;
-use crate::image::util::{random, random_interval};
-use hittable::material::texture::Texture;
+use crate::image::util::random_interval;
+use environment::Environment;
+use film::{AdaptiveSampling, Checkpoint, Film, Pixel, Progressive, RenderSettings};
+use light::Light;
+use hittable::material::texture::{Texture, TextureError, TextureFilter, WrapMode};
+use hittable::lightsampler::LightSampler;
 use hittable::{Hittable, HittableObjects, Material};
 use image::{ImageBuffer, RgbImage};
 use indicatif::ProgressBar;
+use integrator::{BounceLimits, Integrator, Scene};
 use ray::Ray;
 use rayon::prelude::*;
+use sampler::Sampler;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tile::{Tile, Tiles};
 use vector::{Color, Vector};
 
 pub struct Camera {
@@ -35,9 +54,12 @@ pub struct Camera {
     focus_dist: f64,
     defocus_disk_u: Vector,
     defocus_dish_v: Vector,
-    background: Color,
-    sqrt_spp: u32,
-    recip_sqrt_spp: f64,
+    environment: Environment,
+    sampler: Sampler,
+    /// angle subtended by one pixel, used for texture filtering.
+    pixel_spread: f64,
+    /// every random number of a render derives from this.
+    seed: u64,
 }
 
 impl Camera {
@@ -71,8 +93,7 @@ impl Camera {
         let defocus_radius = focus_dist * f64::tan(util::degree_to_radians(defocus_angle / 2.0));
         let defocus_disk_u = u * defocus_radius;
         let defocus_dish_v = v * defocus_radius;
-        let sqrt_spp = (sample_per_pixel as f64).sqrt() as u32;
-        let recip_sqrt_spp = 1.0 / sqrt_spp as f64;
+        let pixel_spread = pixel_delta_v.len() / focus_dist;
         Self {
             viewport_height,
             viewport_width,
@@ -97,24 +118,49 @@ impl Camera {
             focus_dist,
             defocus_disk_u,
             defocus_dish_v,
-            background,
-            sqrt_spp,
-            recip_sqrt_spp,
+            environment: Environment::new_constant(background),
+            sampler: Sampler::default(),
+            pixel_spread,
+            seed: 0,
         }
     }
 
-    pub fn get_ray(&self, idx_width: u32, idx_height: u32, s_width: u32, s_height: u32) -> Ray {
-        let offset = self.sample_square_stratified(s_width, s_height);
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+    }
+
+    /// sets up this thread's random numbers for one sample of one pixel.
+    pub fn start_sample(&self, pixel: u32, sample: u32) {
+        self.sampler
+            .start_sample(self.seed, pixel as u64, sample, self.sample_per_pixel);
+    }
+
+    pub fn get_ray(&self, idx_width: u32, idx_height: u32) -> Ray {
+        let offset = Self::sample_square();
+        self.get_ray_raster(
+            idx_width as f64 + offset.x + 0.5,
+            idx_height as f64 + offset.y + 0.5,
+        )
+    }
+
+    /// ray through a point of the image in pixel units, pixel (i, j) covering
+    /// [i, i + 1) x [j, j + 1).
+    pub fn get_ray_raster(&self, x: f64, y: f64) -> Ray {
         let pixel_sample = self.pixel00_loc
-            + (idx_width as f64 + offset.x) * self.pixel_delta_u
-            + (idx_height as f64 + offset.y) * self.pixel_delta_v;
+            + (x - 0.5) * self.pixel_delta_u
+            + (y - 0.5) * self.pixel_delta_v;
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.center
         } else {
             self.defocus_disk_sample()
         };
         let ray_time = util::random();
-        Ray::new_time(ray_origin, pixel_sample - ray_origin, ray_time)
+        Ray::new_cone(
+            ray_origin,
+            pixel_sample - ray_origin,
+            ray_time,
+            self.pixel_spread,
+        )
     }
 
     fn defocus_disk_sample(&self) -> Vector {
@@ -125,14 +171,6 @@ impl Camera {
     fn sample_square() -> Vector {
         Vector::new(util::random() - 0.5, util::random() - 0.5, 0.0)
     }
-
-    fn sample_square_stratified(&self, s_i: u32, s_j: u32) -> Vector {
-        Vector::new(
-            (s_i as f64 + random()) * self.recip_sqrt_spp - 0.5,
-            (s_j as f64 + random()) * self.recip_sqrt_spp - 0.5,
-            0.,
-        )
-    }
 }
 
 pub struct Image {
@@ -143,6 +181,23 @@ pub struct Image {
     buffer: RgbImage,
     world: HittableObjects,
     lights: HittableObjects,
+    light_sampler: LightSampler,
+    delta_lights: Vec<Light>,
+    integrator: Integrator,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    progressive: Option<Progressive>,
+    preview_path: PathBuf,
+    time_budget: Option<Duration>,
+    checkpoint: Option<Checkpoint>,
+    tiles: Tiles,
+    /// scene case rendered in place of the default of `render` or `render_par`.
+    scene: Option<usize>,
+    /// film loaded by `resume` and what it was rendered with, picked up by the next
+    /// render.
+    resumed: Option<(Film, RenderSettings)>,
+    /// text chunks saved with the image, such as the samples per pixel reached.
+    metadata: Vec<(String, String)>,
+    texture_fallback: bool,
 }
 
 impl Image {
@@ -169,15 +224,133 @@ impl Image {
             buffer: ImageBuffer::new(image_width, image_height),
             world: HittableObjects::new(),
             lights: HittableObjects::new(),
+            light_sampler: LightSampler::empty(),
+            delta_lights: Vec::new(),
+            integrator: Integrator::new_path(BounceLimits::new(max_depth)),
+            adaptive_sampling: None,
+            progressive: None,
+            preview_path: PathBuf::from("preview.png"),
+            time_budget: None,
+            checkpoint: None,
+            tiles: Tiles::new(16),
+            scene: None,
+            resumed: None,
+            metadata: Vec::new(),
+            texture_fallback: false,
+        }
+    }
+
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.camera.set_environment(environment);
+    }
+
+    /// renders with the same seed come out identical.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.camera.seed = seed;
+    }
+
+    /// how samples spread over the pixel, lens, time and each bounce.
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.camera.sampler = sampler;
+    }
+
+    /// light transport algorithm used by the next render.
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+    }
+
+    /// spend samples where pixels are noisy, the samples per pixel becoming the cap.
+    pub fn set_adaptive_sampling(&mut self, adaptive_sampling: AdaptiveSampling) {
+        self.adaptive_sampling = Some(adaptive_sampling);
+    }
+
+    /// render the frame in passes, saving a preview as it converges.
+    pub fn set_progressive(&mut self, progressive: Progressive) {
+        self.progressive = Some(progressive);
+    }
+
+    /// where previews are saved, `preview.png` unless set.
+    pub fn set_preview_path(&mut self, preview_path: impl Into<PathBuf>) {
+        self.preview_path = preview_path.into();
+    }
+
+    /// keep adding passes over the whole frame until `time_budget` runs out instead of
+    /// stopping at the samples per pixel, which still size the sample patterns.
+    pub fn set_time_budget(&mut self, time_budget: Duration) {
+        self.time_budget = Some(time_budget);
+    }
+
+    /// periodically save the accumulation buffer so the render can be resumed.
+    pub fn set_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.checkpoint = Some(checkpoint);
+    }
+
+    /// how `render_par` splits the frame between threads.
+    pub fn set_tiles(&mut self, tiles: Tiles) {
+        self.tiles = tiles;
+    }
+
+    /// which of the `create_scene` cases to render.
+    pub fn set_scene(&mut self, case: usize) {
+        self.scene = Some(case);
+    }
+
+    /// continues the render saved in the checkpoint at `path`, taking over its seed so
+    /// the result matches an uninterrupted render. the samples per pixel, sampler and
+    /// integrator must already be set as they were for the checkpoint.
+    pub fn resume(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let (film, settings) = Film::load_checkpoint(path)?;
+        if let Some(setting) = settings.mismatch(&self.render_settings()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} was rendered with another {}", path.display(), setting),
+            ));
+        }
+        self.camera.seed = settings.seed;
+        self.resumed = Some((film, settings));
+        Ok(())
+    }
+
+    /// what the samples of this render are drawn with, as saved in checkpoints.
+    fn render_settings(&self) -> RenderSettings {
+        RenderSettings {
+            seed: self.camera.seed,
+            samples_per_pixel: self.camera.sample_per_pixel,
+            sampler: self.camera.sampler.fingerprint(),
+            integrator: self.integrator.fingerprint(),
+        }
+    }
+
+    /// point, spot and directional lights, sampled explicitly at every diffuse hit.
+    pub fn add_light(&mut self, light: Light) {
+        self.delta_lights.push(light);
+    }
+
+    /// substitute a placeholder for textures that fail to load instead of aborting the scene.
+    pub fn set_texture_fallback(&mut self, texture_fallback: bool) {
+        self.texture_fallback = texture_fallback;
+    }
+
+    fn load_texture(&self, filename: &str) -> Result<Texture, TextureError> {
+        match Texture::new_image(filename.to_string()) {
+            Ok(texture) => Ok(texture),
+            Err(error) if self.texture_fallback => {
+                log::warn!("{}, using placeholder texture", error);
+                Ok(Texture::new_missing())
+            }
+            Err(error) => Err(error),
         }
     }
-    fn create_scene(&mut self, case: usize) {
+
+    fn create_scene(&mut self, case: usize) -> Result<(), TextureError> {
+        sampler::seed(self.camera.seed);
         match case {
             1 => {
                 self.spheres();
             }
             2 => {
-                self.earth();
+                self.earth()?;
             }
             3 => {
                 self.perlin_noise();
@@ -189,19 +362,31 @@ impl Image {
                 self.simple_lights();
             }
             6 => {
-                self.cornell_box();
+                self.cornell_box(true);
             }
             7 => {
                 self.cornell_smoke();
             }
             8 => {
-                self.final_scene();
+                self.final_scene()?;
+            }
+            9 => {
+                self.cornell_spotlights();
             }
             _ => {}
         }
+        self.collect_lights();
+        Ok(())
+    }
+
+    /// emissive objects in the world are sampled as lights, next to anything the
+    /// scene added to `lights` by hand.
+    fn collect_lights(&mut self) {
+        self.lights.add_hittables(self.world.emissive());
+        self.light_sampler = LightSampler::new(&self.lights);
     }
 
-    fn final_scene(&mut self) {
+    fn final_scene(&mut self) -> Result<(), TextureError> {
         let ground = Material::new_lambertian(Texture::new_solid(Color::new(0.48, 0.83, 0.53)));
         let boxes_per_side = 20;
         for i in 0..boxes_per_side {
@@ -268,14 +453,17 @@ impl Image {
         boundary3.add_medium(0.0001, Color::white());
         self.world.add(boundary3);
 
-        let emat = Material::new_lambertian(Texture::new_image("earthmap.jpg".to_string()));
+        let mut earth_texture = self.load_texture("earthmap.jpg")?;
+        earth_texture.set_filter(TextureFilter::Bilinear);
+        earth_texture.generate_mipmaps();
+        let emat = Material::new_lambertian(earth_texture);
         self.world.add(Hittable::new_sphere(
             Vector::new(400., 200., 400.),
             100.,
             emat,
         ));
 
-        let pertext = Material::new_lambertian(Texture::new_perlin(0.2));
+        let pertext = Material::new_lambertian(Texture::new_perlin(0.2, sampler::next_seed()));
         self.world.add(Hittable::new_sphere(
             Vector::new(220., 280., 300.),
             80.,
@@ -297,6 +485,7 @@ impl Image {
         boxes2.translate(Vector::new(-100., 270., 395.));
 
         self.world.add_hittables(boxes2);
+        Ok(())
     }
     fn cornell_smoke(&mut self) {
         let red = Material::new_lambertian(Texture::new_solid(Color::new(0.65, 0.05, 0.05)));
@@ -377,7 +566,7 @@ impl Image {
         box2.translate(Vector::new(130., 0., 65.));
         self.world.add_hittables(box2);
     }
-    fn cornell_box(&mut self) {
+    fn cornell_box(&mut self, ceiling_light: bool) {
         let red = Material::new_lambertian(Texture::new_solid(Color::new(0.65, 0.05, 0.05)));
         let white = Material::new_lambertian(Texture::new_solid(Color::new(0.73, 0.73, 0.73)));
         let green = Material::new_lambertian(Texture::new_solid(Color::new(0.12, 0.45, 0.15)));
@@ -401,12 +590,6 @@ impl Image {
             Vector::new(0., 0., -105.),
             light,
         );
-        let light_object = Hittable::new_quad(
-            Vector::new(343., 554., 332.),
-            Vector::new(-130., 0., 0.),
-            Vector::new(0., 0., -105.),
-            Material::default(),
-        );
         let q4 = Hittable::new_quad(
             Vector::new(0., 0., 0.),
             Vector::new(555., 0., 0.),
@@ -428,8 +611,9 @@ impl Image {
 
         self.world.add(q1);
         self.world.add(q2);
-        self.world.add(q3);
-        self.lights.add(light_object);
+        if ceiling_light {
+            self.world.add(q3);
+        }
         self.world.add(q4);
         self.world.add(q5);
         self.world.add(q6);
@@ -458,6 +642,22 @@ impl Image {
         self.world.add(glass);
 
     }
+    /// the cornell box with delta lights in place of its ceiling panel, a warm spot
+    /// on the glass sphere and a dimmer point light filling in the rest.
+    fn cornell_spotlights(&mut self) {
+        self.cornell_box(false);
+        self.add_light(Light::new_spot(
+            Vector::new(420., 500., 80.),
+            Vector::new(165., 90., 165.),
+            Color::new(150000., 130000., 100000.),
+            25.,
+            15.,
+        ));
+        self.add_light(Light::new_point(
+            Vector::new(278., 400., 278.),
+            Color::new(20000., 20000., 23000.),
+        ));
+    }
     fn simple_lights(&mut self) {
         self.perlin_noise();
         let difflight = Material::new_diffuse_light(Texture::new_solid(Color::new(4.0, 4., 4.)));
@@ -512,7 +712,7 @@ impl Image {
         self.world.add(lower);
     }
     fn perlin_noise(&mut self) {
-        let perlin_texture = Texture::new_perlin(4.0);
+        let perlin_texture = Texture::new_perlin(4.0, sampler::next_seed());
         let perlin_surface = Material::new_lambertian(perlin_texture);
         self.world.add(Hittable::new_sphere(
             Vector::new(0.0, -1000.0, 0.0),
@@ -525,11 +725,15 @@ impl Image {
             perlin_surface,
         ));
     }
-    fn earth(&mut self) {
-        let earth_texture = Texture::new_image("earthmap.jpg".to_string());
+    fn earth(&mut self) -> Result<(), TextureError> {
+        let mut earth_texture = self.load_texture("earthmap.jpg")?;
+        earth_texture.set_filter(TextureFilter::Bilinear);
+        earth_texture.set_wrap(WrapMode::Repeat);
+        earth_texture.generate_mipmaps();
         let earth_surface = Material::new_lambertian(earth_texture);
         let globe = Hittable::new_sphere(Vector::new(0.0, 0.0, 0.0), 2.0, earth_surface);
         self.world.add(globe);
+        Ok(())
     }
     fn spheres(&mut self) {
         let ground_material = Material::new_lambertian(Texture::new_checker(
@@ -590,23 +794,304 @@ impl Image {
         self.world.add(hittable_3);
     }
 
-    pub fn render(&mut self) {
-        let case = 8;
-        self.create_scene(case);
+    /// fills the buffer for integrators that render whole images, false if the
+    /// integrator works per ray.
+    fn render_image(&mut self) -> bool {
+        let scene = Scene {
+            world: &self.world,
+            environment: &self.camera.environment,
+            lights: &self.light_sampler,
+            delta_lights: &self.delta_lights,
+        };
+        let Some(colors) =
+            self.integrator
+                .render_image(&self.camera, &scene, self.image_width, self.image_height)
+        else {
+            return false;
+        };
+        self.warn_checkpoint_ignored("this integrator");
+        for (index, mut color) in colors.into_iter().enumerate() {
+            let index = index as u32;
+            self.buffer.put_pixel(
+                index % self.image_width,
+                index / self.image_width,
+                color.as_pixel(),
+            );
+        }
+        let samples = self.camera.sample_per_pixel;
+        self.set_metadata("samples per pixel", samples.to_string());
+        true
+    }
+
+    /// samples pixels in passes until each has converged, false if adaptive sampling
+    /// is off.
+    fn render_adaptive(&mut self) -> bool {
+        let Some(adaptive) = self.adaptive_sampling else {
+            return false;
+        };
+        self.warn_checkpoint_ignored("adaptive sampling");
+        let scene = Scene {
+            world: &self.world,
+            environment: &self.camera.environment,
+            lights: &self.light_sampler,
+            delta_lights: &self.delta_lights,
+        };
+        // every pixel takes at least one sample, even with no samples per pixel set.
+        let max_samples = self.camera.sample_per_pixel.max(1);
+        let pixel_count = (self.image_width * self.image_height) as usize;
+        let mut film = Film::new(self.image_width, self.image_height);
+        let mut active = vec![true; pixel_count];
+        let pb = ProgressBar::new(max_samples as u64 * pixel_count as u64);
+        let (width, height) = (self.image_width as usize, self.image_height as usize);
+        while active.contains(&true) {
+            film.pixels_mut()
+                .par_iter_mut()
+                .zip(active.par_iter())
+                .enumerate()
+                .filter(|(_, (_, active))| **active)
+                .for_each(|(index, (pixel, _))| {
+                    let (i, j) = (
+                        index as u32 / self.image_width,
+                        index as u32 % self.image_width,
+                    );
+                    let start = pixel.samples();
+                    let end = if start == 0 {
+                        adaptive.min_samples
+                    } else {
+                        start + adaptive.pass_samples.max(1)
+                    }
+                    .clamp(1, max_samples);
+                    for s in start..end {
+                        self.camera.start_sample(index as u32, s);
+                        pixel.add(self.integrator.li(&self.camera.get_ray(j, i), &scene));
+                    }
+                    pb.inc((end - start) as u64);
+                });
+            // a pixel keeps going while any neighbour is noisy, so a region that has
+            // seen only some of its rare bright paths is not left dark.
+            let errors = film
+                .pixels()
+                .iter()
+                .map(Pixel::relative_error)
+                .collect::<Vec<_>>();
+            for (index, active) in active.iter_mut().enumerate() {
+                let (y, x) = (index / width, index % width);
+                let error = (y.saturating_sub(1)..(y + 2).min(height))
+                    .flat_map(|y| (x.saturating_sub(1)..(x + 2).min(width)).map(move |x| (y, x)))
+                    .map(|(y, x)| errors[y * width + x])
+                    .fold(0.0, f64::max);
+                *active =
+                    film.pixels()[index].samples() < max_samples && error > adaptive.tolerance;
+            }
+        }
+        pb.finish();
+
+        let samples = film.pixels().iter().map(Pixel::samples);
+        let (min, max) = (samples.clone().min(), samples.clone().max());
+        let mean = samples.map(f64::from).sum::<f64>() / pixel_count as f64;
+        self.set_metadata("samples per pixel", format!("{:.1}", mean));
+        if let (Some(min), Some(max)) = (min, max) {
+            self.set_metadata("samples per pixel range", format!("{} to {}", min, max));
+        }
+        self.buffer = film.to_image();
+        if adaptive.heatmap {
+            let heatmap = RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+                let pixel = film.pixels()[(y * self.image_width + x) as usize];
+                let t = pixel.samples() as f64 / max_samples as f64;
+                Color::new(t, t, t).as_pixel()
+            });
+            // only a diagnostic, it must never cost the image itself.
+            if let Err(error) = heatmap.save("heatmap.png") {
+                log::warn!("could not save heatmap.png: {}", error);
+            }
+        }
+        true
+    }
+
+    /// renders every pixel a pass at a time, saving previews and checkpoints along the
+    /// way if asked to, false if none of progressive rendering, a time budget,
+    /// checkpoints or resuming is set.
+    fn render_progressive(&mut self) -> bool {
+        let progressive = match self.progressive {
+            Some(progressive) => progressive,
+            None if self.time_budget.is_some()
+                || self.checkpoint.is_some()
+                || self.resumed.is_some() =>
+            {
+                Progressive::new(1)
+            }
+            None => return false,
+        };
+        let settings = self.render_settings();
+        let mut film = match self.resumed.take() {
+            Some((film, _))
+                if film.width() != self.image_width || film.height() != self.image_height =>
+            {
+                log::warn!("checkpoint is for another image size, starting over");
+                Film::new(self.image_width, self.image_height)
+            }
+            Some((_, resumed)) if resumed != settings => {
+                log::warn!(
+                    "render settings changed since the checkpoint was loaded, starting over"
+                );
+                Film::new(self.image_width, self.image_height)
+            }
+            Some((film, _)) => film,
+            None => Film::new(self.image_width, self.image_height),
+        };
+        let previews = self.progressive.is_some();
+        let scene = Scene {
+            world: &self.world,
+            environment: &self.camera.environment,
+            lights: &self.light_sampler,
+            delta_lights: &self.delta_lights,
+        };
+        let max_samples = match self.time_budget {
+            Some(_) => u32::MAX,
+            None => self.camera.sample_per_pixel,
+        };
+        let pb = match self.time_budget {
+            Some(budget) => ProgressBar::new(budget.as_millis() as u64),
+            None => ProgressBar::new(max_samples as u64),
+        };
+        let start = Instant::now();
+        let mut last_preview = start;
+        let mut last_checkpoint = start;
+        let mut last_pass = Duration::ZERO;
+        let mut samples = film.pixels().iter().map(Pixel::samples).min().unwrap_or(0);
+        while samples < max_samples {
+            // stop when another pass as long as the last would overrun the budget.
+            if let Some(budget) = self.time_budget {
+                if samples > 0 && start.elapsed() + last_pass > budget {
+                    break;
+                }
+            }
+            let pass_start = Instant::now();
+            let end = (samples + progressive.pass_samples.max(1)).min(max_samples);
+            film.pixels_mut()
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(index, pixel)| {
+                    let (i, j) = (
+                        index as u32 / self.image_width,
+                        index as u32 % self.image_width,
+                    );
+                    for s in samples..end {
+                        self.camera.start_sample(index as u32, s);
+                        pixel.add(self.integrator.li(&self.camera.get_ray(j, i), &scene));
+                    }
+                });
+            samples = end;
+            last_pass = pass_start.elapsed();
+            match self.time_budget {
+                Some(_) => pb.set_position(start.elapsed().as_millis() as u64),
+                None => pb.set_position(samples as u64),
+            }
+            let preview_due = progressive
+                .preview_interval
+                .is_none_or(|interval| last_preview.elapsed() >= interval);
+            if previews && preview_due && samples < max_samples {
+                Self::save_preview(&film.to_image(), &self.preview_path);
+                last_preview = Instant::now();
+            }
+            if let Some(checkpoint) = &self.checkpoint {
+                if last_checkpoint.elapsed() >= checkpoint.interval {
+                    self.save_checkpoint(&film, checkpoint);
+                    last_checkpoint = Instant::now();
+                }
+            }
+        }
+        pb.finish();
+        if let Some(checkpoint) = &self.checkpoint {
+            self.save_checkpoint(&film, checkpoint);
+        }
+        log::info!(
+            "rendered {} samples per pixel in {:?}",
+            samples,
+            start.elapsed()
+        );
+        self.set_metadata("samples per pixel", samples.to_string());
+        self.buffer = film.to_image();
+        true
+    }
+
+    /// checkpoints only cover pass based rendering, which `what` takes the place of.
+    fn warn_checkpoint_ignored(&self, what: &str) {
+        if self.checkpoint.is_some() || self.resumed.is_some() {
+            log::warn!(
+                "checkpoints are not supported with {}, rendering from scratch without them",
+                what
+            );
+        }
+    }
+
+    /// a failed checkpoint only costs progress if the render is interrupted later, so
+    /// it is reported rather than stopping the render.
+    fn save_checkpoint(&self, film: &Film, checkpoint: &Checkpoint) {
+        if let Err(error) = film.save_checkpoint(&checkpoint.path, &self.render_settings()) {
+            log::warn!(
+                "could not save checkpoint {}: {}",
+                checkpoint.path.display(),
+                error
+            );
+        }
+    }
+
+    /// a preview is only a look at the render so far, so failing to save one is
+    /// reported rather than stopping the render.
+    fn save_preview(preview: &RgbImage, path: &Path) {
+        if let Err(error) = preview.save(path) {
+            log::warn!("could not save preview {}: {}", path.display(), error);
+        }
+    }
+
+    /// sets a text chunk saved with the image, replacing what an earlier render set.
+    fn set_metadata(&mut self, keyword: &str, text: String) {
+        self.metadata.retain(|(key, _)| key != keyword);
+        self.metadata.push((keyword.to_string(), text));
+    }
+
+    /// saves the buffer as a png carrying the render's metadata.
+    fn save_image(&self, filename: &str) {
+        let file = BufWriter::new(File::create(filename).unwrap());
+        let mut encoder = png::Encoder::new(file, self.image_width, self.image_height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (keyword, text) in &self.metadata {
+            encoder
+                .add_text_chunk(keyword.clone(), text.clone())
+                .unwrap();
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(self.buffer.as_raw()).unwrap();
+    }
+
+    pub fn render(&mut self) -> Result<(), TextureError> {
+        let case = self.scene.unwrap_or(8);
+        self.create_scene(case)?;
+        self.metadata.clear();
+        if self.render_image() || self.render_adaptive() || self.render_progressive() {
+            self.save_image("image.png");
+            self.world.clear();
+            self.lights.clear();
+            return Ok(());
+        }
         // self.world.init_bvh();
+        let scene = Scene {
+            world: &self.world,
+            environment: &self.camera.environment,
+            lights: &self.light_sampler,
+            delta_lights: &self.delta_lights,
+        };
         let pb = ProgressBar::new((self.image_height) as u64);
         for i in 0..self.image_height {
             for j in 0..self.image_width {
                 let mut pixel_color = Color::black();
                 for s in 0..self.camera.sample_per_pixel {
+                    self.camera.start_sample(i * self.image_width + j, s);
                     pixel_color = pixel_color
                         + self.camera.pixel_sample_scale
-                            * self.camera.get_ray(j, i, s / 2, s / 2).color(
-                                self.camera.max_depth,
-                                &self.world,
-                                self.camera.background,
-                                &self.lights,
-                            );
+                            * self.integrator.li(&self.camera.get_ray(j, i), &scene);
                 }
                 self.buffer.put_pixel(j, i, pixel_color.as_pixel());
             }
@@ -618,65 +1103,296 @@ impl Image {
                     .unwrap();
             }
         }
-        self.buffer.save("image.png").unwrap();
+        let samples = self.camera.sample_per_pixel;
+        self.set_metadata("samples per pixel", samples.to_string());
+        self.save_image("image.png");
         self.world.clear();
+        self.lights.clear();
         pb.finish_with_message(format!("Total Time Spent: {:?}", pb.elapsed()));
+        Ok(())
     }
 
-    pub fn render_par(&mut self) {
-        let case = 6;
-        self.create_scene(case);
-        let pb = ProgressBar::new((self.image_height * self.image_width) as u64);
-        let mut pixels = vec![];
-        let mut samples = vec![];
-        for i in 0..self.image_height {
-            for j in 0..self.image_width {
-                pixels.push((i, j));
-            }
+    pub fn render_par(&mut self) -> Result<(), TextureError> {
+        let case = self.scene.unwrap_or(6);
+        self.create_scene(case)?;
+        self.metadata.clear();
+        if !(self.render_image() || self.render_adaptive() || self.render_progressive()) {
+            self.render_tiles();
         }
-        for si in 0..self.camera.sqrt_spp {
-            for sj in 0..self.camera.sqrt_spp {
-                samples.push((si, sj));
+        self.save_image("image.png");
+        self.world.clear();
+        self.lights.clear();
+        Ok(())
+    }
+
+    /// renders the frame tile by tile across threads, each pixel taking all its samples.
+    fn render_tiles(&mut self) {
+        let scene = Scene {
+            world: &self.world,
+            environment: &self.camera.environment,
+            lights: &self.light_sampler,
+            delta_lights: &self.delta_lights,
+        };
+        let (camera, integrator, width) = (&self.camera, &self.integrator, self.image_width);
+        let tiles = self.tiles.split(self.image_width, self.image_height);
+        let pb = ProgressBar::new(tiles.len() as u64);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            // tiles are pulled in order as threads free up, finished ones sent back here.
+            scope.spawn(move || {
+                tiles
+                    .into_iter()
+                    .par_bridge()
+                    .for_each_with(sender, |sender, tile| {
+                        let pixels = Self::render_tile(camera, integrator, &scene, width, tile);
+                        sender.send((tile, pixels)).unwrap();
+                    });
+            });
+            let mut last_preview = Instant::now();
+            for (tile, pixels) in receiver {
+                for ((x, y), pixel) in tile.pixels().zip(pixels) {
+                    self.buffer.put_pixel(x, y, pixel.color().as_pixel());
+                }
+                pb.inc(1);
+                let preview_due = self
+                    .tiles
+                    .preview_interval
+                    .is_some_and(|interval| last_preview.elapsed() >= interval);
+                if preview_due {
+                    Self::save_preview(&self.buffer, &self.preview_path);
+                    last_preview = Instant::now();
+                }
+            }
+        });
+        pb.finish();
+        let samples = self.camera.sample_per_pixel;
+        self.set_metadata("samples per pixel", samples.to_string());
+    }
+
+    /// every sample of every pixel in `tile`, in its own buffer so threads never share
+    /// pixels.
+    fn render_tile(
+        camera: &Camera,
+        integrator: &Integrator,
+        scene: &Scene,
+        width: u32,
+        tile: Tile,
+    ) -> Vec<Pixel> {
+        let mut pixels = vec![Pixel::default(); (tile.width() * tile.height()) as usize];
+        for ((x, y), pixel) in tile.pixels().zip(pixels.iter_mut()) {
+            for s in 0..camera.sample_per_pixel {
+                camera.start_sample(y * width + x, s);
+                pixel.add(integrator.li(&camera.get_ray(x, y), scene));
             }
         }
-        let pixels = pixels
-            .into_par_iter()
-            .map(|(i, j)| {
-                let colors = samples
-                    .clone()
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+
+    fn render_noise(seed: u64) -> RgbImage {
+        let mut image = Image::new(1.0, 16, 4, 5);
+        image.camera = Camera::new(
+            16.0,
+            16.0,
+            4,
+            5,
+            20.0,
+            Vector::new(13.0, 2.0, 3.0),
+            Vector::zero(),
+            Vector::new(0.0, 1.0, 0.0),
+            0.0,
+            10.0,
+            Color::new(0.7, 0.8, 1.0),
+        );
+        image.set_seed(seed);
+        image.create_scene(3).unwrap();
+        image.render_tiles();
+        image.buffer
+    }
+
+    #[test]
+    fn same_seed_renders_identically() {
+        assert_eq!(render_noise(7), render_noise(7));
+        assert_ne!(render_noise(7), render_noise(8));
+    }
+
+    #[test]
+    fn adaptive_sampling_without_samples_per_pixel() {
+        let mut image = Image::new(1.0, 4, 0, 2);
+        image.set_adaptive_sampling(AdaptiveSampling::new(4, 0.05));
+        image.create_scene(6).unwrap();
+        assert!(image.render_adaptive());
+    }
+
+    #[test]
+    fn samples_per_pixel_recorded_once() {
+        let mut image = Image::new(1.0, 4, 8, 2);
+        image.create_scene(6).unwrap();
+        image.render_tiles();
+        image.set_adaptive_sampling(AdaptiveSampling::new(4, 0.05));
+        image.render_adaptive();
+        image.render_adaptive();
+        let entries = image
+            .metadata
+            .iter()
+            .filter(|(keyword, _)| keyword == "samples per pixel")
+            .count();
+        assert_eq!(entries, 1);
+    }
+
+    /// mean linear radiance of a `width` square render of the cornell box.
+    fn cornell_mean(integrator: Integrator, width: u32, samples: u32) -> Color {
+        let mut image = Image::new(1.0, width, samples, 50);
+        image.set_integrator(integrator);
+        image.create_scene(6).unwrap();
+        let scene = Scene {
+            world: &image.world,
+            environment: &image.camera.environment,
+            lights: &image.light_sampler,
+            delta_lights: &image.delta_lights,
+        };
+        let (camera, integrator) = (&image.camera, &image.integrator);
+        let pixels = integrator
+            .render_image(camera, &scene, width, width)
+            .unwrap_or_else(|| {
+                (0..width * width)
                     .into_par_iter()
-                    .map(|(si, sj)| {
-                        self.camera.get_ray(j, i, sj, si).color(
-                            self.camera.max_depth,
-                            &self.world,
-                            self.camera.background,
-                            &self.lights,
-                        )
+                    .map(|index| {
+                        let mut pixel = Pixel::default();
+                        for s in 0..samples {
+                            camera.start_sample(index, s);
+                            let ray = camera.get_ray(index % width, index / width);
+                            pixel.add(integrator.li(&ray, &scene));
+                        }
+                        pixel.color()
                     })
-                    .collect::<Vec<Color>>();
-                let mut pixel_color = self.camera.pixel_sample_scale
-                    * colors
-                        .into_iter()
-                        .fold(Color::black(), |acc, color| acc + color);
+                    .collect()
+            });
+        let sum = pixels
+            .into_iter()
+            .fold(Color::black(), |sum, color| sum + color);
+        sum / (width * width) as f64
+    }
 
-                pb.inc(1);
-                if i == j {
-                    let eta = pb.eta();
-                    let elapsed = pb.elapsed();
-                    pb.println(format!(
-                        "ETA: {:?}, Elapsed: {:?}, Total: {:?}",
-                        eta,
-                        elapsed,
-                        eta + elapsed
-                    ));
-                }
-                (i, j, pixel_color.as_pixel())
-            })
-            .collect::<Vec<_>>();
-        for (i, j, pixel) in pixels {
-            self.buffer.put_pixel(j, i, pixel);
-        }
-        self.buffer.save("image.png").unwrap();
-        self.world.clear();
+    /// the path traced cornell box, close enough to converged to check the other
+    /// integrators against.
+    fn cornell_reference() -> Color {
+        static REFERENCE: OnceLock<Color> = OnceLock::new();
+        *REFERENCE
+            .get_or_init(|| cornell_mean(Integrator::new_path(BounceLimits::new(50)), 32, 1024))
+    }
+
+    fn assert_converged(integrator: Integrator, samples: u32) {
+        let luminance = cornell_mean(integrator, 32, samples).luminance();
+        let reference = cornell_reference().luminance();
+        let error = (luminance - reference).abs() / reference;
+        assert!(
+            error < 0.015,
+            "mean luminance {} is {:.2}% off the path tracer's {}",
+            luminance,
+            100.0 * error,
+            reference
+        );
+    }
+
+    #[test]
+    #[ignore = "slow in debug builds, run with cargo test --release -- --ignored"]
+    fn bidirectional_converges_to_path() {
+        assert_converged(Integrator::new_bidirectional(50), 512);
+    }
+
+    #[test]
+    #[ignore = "slow in debug builds, run with cargo test --release -- --ignored"]
+    fn photon_mapping_converges_to_path() {
+        assert_converged(Integrator::new_photon_mapping(20_000, 20.0, 50), 64);
+    }
+
+    #[test]
+    #[ignore = "slow in debug builds, run with cargo test --release -- --ignored"]
+    fn metropolis_converges_to_path() {
+        let limits = BounceLimits::new(50);
+        assert_converged(
+            Integrator::new_metropolis(limits, 1_000_000, 4096, 0.01, 0.3),
+            1024,
+        );
+    }
+
+    #[test]
+    fn unwritable_preview_does_not_stop_the_render() {
+        let mut image = Image::new(1.0, 4, 3, 2);
+        image.set_progressive(Progressive::new(1));
+        image.set_preview_path(std::env::temp_dir().join("missing-dir").join("preview.png"));
+        image.create_scene(6).unwrap();
+        assert!(image.render_progressive());
+        let samples = ("samples per pixel".to_string(), "3".to_string());
+        assert!(image.metadata.contains(&samples));
+    }
+
+    #[test]
+    fn unwritable_tile_preview_does_not_stop_the_render() {
+        let mut image = Image::new(1.0, 8, 2, 2);
+        let mut tiles = Tiles::new(2);
+        tiles.preview_interval = Some(Duration::ZERO);
+        image.set_tiles(tiles);
+        image.set_preview_path(std::env::temp_dir().join("missing-dir").join("preview.png"));
+        image.create_scene(6).unwrap();
+        image.render_tiles();
+        let samples = ("samples per pixel".to_string(), "2".to_string());
+        assert!(image.metadata.contains(&samples));
+    }
+
+    fn checkpoint_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.ckpt", name, std::process::id()))
+    }
+
+    #[test]
+    fn resumed_render_matches_uninterrupted() {
+        let full_path = checkpoint_path("full");
+        let mut full = Image::new(1.0, 8, 8, 3);
+        full.set_checkpoint(Checkpoint::new(&full_path, Duration::MAX));
+        full.create_scene(6).unwrap();
+        assert!(full.render_progressive());
+        std::fs::remove_file(&full_path).unwrap();
+
+        // a zero budget stops after the first pass.
+        let path = checkpoint_path("resume");
+        let mut interrupted = Image::new(1.0, 8, 8, 3);
+        interrupted.set_time_budget(Duration::ZERO);
+        interrupted.set_checkpoint(Checkpoint::new(&path, Duration::MAX));
+        interrupted.create_scene(6).unwrap();
+        interrupted.render_progressive();
+
+        let mut resumed = Image::new(1.0, 8, 8, 3);
+        resumed.resume(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        resumed.create_scene(6).unwrap();
+        assert!(resumed.render_progressive());
+        assert_eq!(full.buffer, resumed.buffer);
+    }
+
+    #[test]
+    fn resume_rejects_other_settings() {
+        let path = checkpoint_path("mismatch");
+        let mut image = Image::new(1.0, 4, 4, 2);
+        image.set_checkpoint(Checkpoint::new(&path, Duration::MAX));
+        image.create_scene(6).unwrap();
+        image.render_progressive();
+
+        let mut more_samples = Image::new(1.0, 4, 8, 2);
+        assert!(more_samples.resume(&path).is_err());
+        let mut other_sampler = Image::new(1.0, 4, 4, 2);
+        other_sampler.set_sampler(Sampler::new_sobol());
+        assert!(other_sampler.resume(&path).is_err());
+        let mut other_integrator = Image::new(1.0, 4, 4, 2);
+        other_integrator.set_integrator(Integrator::new_direct_lighting(2));
+        assert!(other_integrator.resume(&path).is_err());
+        let mut same = Image::new(1.0, 4, 4, 2);
+        assert!(same.resume(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,396 @@
+use crate::image::hittable::material::onb::ONB;
+use crate::image::hittable::material::texture::{ImageData, TextureError};
+use crate::image::util::{self, Distribution2D};
+use crate::image::vector::{Color, Vector};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// angular radius of the sun disk, in radians.
+const SUN_ANGULAR_RADIUS: f64 = 0.004_65;
+/// sun illuminance above the atmosphere in klx, matching the sky's kcd/m^2 luminance.
+const SUN_ILLUMINANCE: f64 = 120.0;
+/// fraction of sky samples spent on the sun disk.
+const SUN_SAMPLE_PROBABILITY: f64 = 0.5;
+
+#[derive(Clone)]
+enum EnvironmentType {
+    Constant {
+        color: Color,
+    },
+    /// equirectangular image, importance sampled by luminance.
+    Map {
+        image: ImageData,
+        distribution: Arc<Distribution2D>,
+        intensity: f64,
+        sin_theta: f64,
+        cos_theta: f64,
+    },
+    /// Preetham analytic daylight with a sun disk, luminance in kcd/m^2 times `intensity`.
+    Sky {
+        sun: Vector,
+        /// perez coefficients A to E for luminance and the two chromaticities.
+        perez: [[f64; 5]; 3],
+        /// zenith luminance and chromaticity, divided by the perez value at the zenith.
+        zenith: [f64; 3],
+        sun_radiance: Color,
+        cos_sun_max: f64,
+        intensity: f64,
+    },
+}
+
+/// radiance arriving from infinitely far away, seen by rays that miss the world.
+#[derive(Clone)]
+pub struct Environment {
+    environment: EnvironmentType,
+}
+
+impl Environment {
+    pub fn new_constant(color: Color) -> Self {
+        Self {
+            environment: EnvironmentType::Constant { color },
+        }
+    }
+
+    /// `rotation` turns the map about the y axis, in degrees.
+    pub fn new_map(filename: String, rotation: f64, intensity: f64) -> Result<Self, TextureError> {
+        let image = ImageData::new(filename)?;
+        let width = image.width() as usize;
+        let height = image.height() as usize;
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            // rows near the poles cover less solid angle.
+            let sin_polar = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                func.push(image.pixel_data(x as u32, y as u32).luminance() * sin_polar);
+            }
+        }
+        let radians = util::degree_to_radians(rotation);
+        Ok(Self {
+            environment: EnvironmentType::Map {
+                image,
+                distribution: Arc::new(Distribution2D::new(&func, width, height)),
+                intensity,
+                sin_theta: radians.sin(),
+                cos_theta: radians.cos(),
+            },
+        })
+    }
+
+    /// `elevation` is the sun's angle above the horizon and `azimuth` its angle from +z
+    /// towards +x, both in degrees. `turbidity` ranges from about 2 (clear) to 10 (hazy).
+    pub fn new_sky(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64) -> Self {
+        let elevation = util::degree_to_radians(elevation.clamp(0.0, 90.0));
+        let azimuth = util::degree_to_radians(azimuth);
+        let sun = Vector::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+        let sun_zenith = PI / 2.0 - elevation;
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_zenith);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let th = sun_zenith;
+        let (th2, th3) = (th * th, th * th * th);
+        let zenith_cx = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_cy = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+        let zenith = [
+            zenith_y.max(0.0) / Self::perez(&perez[0], 0.0, sun_zenith),
+            zenith_cx / Self::perez(&perez[1], 0.0, sun_zenith),
+            zenith_cy / Self::perez(&perez[2], 0.0, sun_zenith),
+        ];
+
+        let cos_sun_max = SUN_ANGULAR_RADIUS.cos();
+        let solid_angle = 2.0 * PI * (1.0 - cos_sun_max);
+        let sun_radiance =
+            (SUN_ILLUMINANCE / solid_angle) * Self::sun_transmittance(sun_zenith, turbidity);
+        Self {
+            environment: EnvironmentType::Sky {
+                sun,
+                perez,
+                zenith,
+                sun_radiance,
+                cos_sun_max,
+                intensity,
+            },
+        }
+    }
+
+    fn perez(coefficients: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = *coefficients;
+        (1.0 + a * f64::exp(b / theta.cos().max(0.01)))
+            * (1.0 + c * f64::exp(d * gamma) + e * gamma.cos().powi(2))
+    }
+
+    /// rayleigh and aerosol extinction along the sun's path at 680, 550 and 440 nm.
+    fn sun_transmittance(sun_zenith: f64, turbidity: f64) -> Color {
+        let zenith_degrees = sun_zenith.to_degrees().min(93.0);
+        let air_mass = 1.0 / (sun_zenith.cos() + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+        let beta = 0.04608 * turbidity - 0.04586;
+        let channel = |wavelength: f64, rayleigh: f64| {
+            let aerosol = beta * wavelength.powf(-1.3);
+            f64::exp(-air_mass * (rayleigh + aerosol))
+        };
+        Color::new(
+            channel(0.68, 0.0447),
+            channel(0.55, 0.0975),
+            channel(0.44, 0.236),
+        )
+    }
+
+    fn sky_value(&self, direction: Vector) -> Color {
+        let EnvironmentType::Sky {
+            sun,
+            perez,
+            zenith,
+            sun_radiance,
+            cos_sun_max,
+            intensity,
+            ..
+        } = &self.environment
+        else {
+            return Color::black();
+        };
+        let d = direction.unit_vector();
+        // below the horizon repeats the horizon color.
+        let theta = f64::acos(d.y.clamp(0.0, 1.0));
+        let cos_gamma = d.dot(*sun).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let luminance = zenith[0] * Self::perez(&perez[0], theta, gamma);
+        let cx = zenith[1] * Self::perez(&perez[1], theta, gamma);
+        let cy = zenith[2] * Self::perez(&perez[2], theta, gamma);
+        let sky = if cy > 0.0 {
+            let x = cx / cy * luminance;
+            let z = (1.0 - cx - cy) / cy * luminance;
+            Color::new(
+                (3.2406 * x - 1.5372 * luminance - 0.4986 * z).max(0.0),
+                (-0.9689 * x + 1.8758 * luminance + 0.0415 * z).max(0.0),
+                (0.0557 * x - 0.2040 * luminance + 1.0570 * z).max(0.0),
+            )
+        } else {
+            Color::black()
+        };
+        let sun_disk = if cos_gamma >= *cos_sun_max && d.y > 0.0 {
+            *sun_radiance
+        } else {
+            Color::black()
+        };
+        *intensity * (sky + sun_disk)
+    }
+
+    /// whether the environment should be sampled explicitly as a light.
+    pub fn is_sampled(&self) -> bool {
+        matches!(
+            self.environment,
+            EnvironmentType::Map { .. } | EnvironmentType::Sky { .. }
+        )
+    }
+
+    pub fn value(&self, direction: Vector) -> Color {
+        match &self.environment {
+            EnvironmentType::Constant { color } => *color,
+            EnvironmentType::Map {
+                image, intensity, ..
+            } => {
+                let (x, y) = self.direction_to_image(direction);
+                let i = (x * image.width() as f64) as u32;
+                let j = (y * image.height() as f64) as u32;
+                *intensity * image.pixel_data(i, j)
+            }
+            EnvironmentType::Sky { .. } => self.sky_value(direction),
+        }
+    }
+
+    pub fn random(&self) -> Vector {
+        match &self.environment {
+            EnvironmentType::Map { distribution, .. } => {
+                let (x, y, _) = distribution.sample_continuous(util::random(), util::random());
+                self.image_to_direction(x, y)
+            }
+            EnvironmentType::Sky {
+                sun, cos_sun_max, ..
+            } => {
+                if util::random() < SUN_SAMPLE_PROBABILITY {
+                    let cos_theta = 1.0 - util::random() * (1.0 - cos_sun_max);
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let phi = 2.0 * PI * util::random();
+                    ONB::new(*sun).transform(Vector::new(
+                        sin_theta * phi.cos(),
+                        sin_theta * phi.sin(),
+                        cos_theta,
+                    ))
+                } else {
+                    Vector::random_on_unit_sphere()
+                }
+            }
+            _ => Vector::random_unit_vector(),
+        }
+    }
+
+    pub fn pdf_value(&self, direction: Vector) -> f64 {
+        match &self.environment {
+            EnvironmentType::Map { distribution, .. } => {
+                let unit = direction.unit_vector();
+                let sin_polar = (1.0 - unit.y * unit.y).max(0.0).sqrt();
+                if sin_polar == 0.0 {
+                    return 0.0;
+                }
+                let (x, y) = self.direction_to_image(unit);
+                distribution.pdf(x, y) / (2.0 * PI * PI * sin_polar)
+            }
+            EnvironmentType::Sky {
+                sun, cos_sun_max, ..
+            } => {
+                let sphere_pdf = (1.0 - SUN_SAMPLE_PROBABILITY) / (4.0 * PI);
+                if direction.unit_vector().dot(*sun) >= *cos_sun_max {
+                    let cone_pdf = 1.0 / (2.0 * PI * (1.0 - cos_sun_max));
+                    sphere_pdf + SUN_SAMPLE_PROBABILITY * cone_pdf
+                } else {
+                    sphere_pdf
+                }
+            }
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+
+    /// image coordinates in the unit square, y pointing down from the zenith.
+    fn direction_to_image(&self, direction: Vector) -> (f64, f64) {
+        let (sin_theta, cos_theta) = self.rotation();
+        let d = direction.unit_vector();
+        let local = Vector::new(
+            cos_theta * d.x - sin_theta * d.z,
+            d.y,
+            sin_theta * d.x + cos_theta * d.z,
+        );
+        let polar = f64::acos((-local.y).clamp(-1.0, 1.0));
+        let phi = f64::atan2(-local.z, local.x) + PI;
+        (phi / (2.0 * PI), 1.0 - polar / PI)
+    }
+
+    fn image_to_direction(&self, x: f64, y: f64) -> Vector {
+        let (sin_theta, cos_theta) = self.rotation();
+        let polar = (1.0 - y) * PI;
+        let phi = x * 2.0 * PI - PI;
+        let local = Vector::new(
+            polar.sin() * phi.cos(),
+            -polar.cos(),
+            -polar.sin() * phi.sin(),
+        );
+        Vector::new(
+            cos_theta * local.x + sin_theta * local.z,
+            local.y,
+            -sin_theta * local.x + cos_theta * local.z,
+        )
+    }
+
+    fn rotation(&self) -> (f64, f64) {
+        match self.environment {
+            EnvironmentType::Map {
+                sin_theta,
+                cos_theta,
+                ..
+            } => (sin_theta, cos_theta),
+            _ => (0.0, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// the pdf over the sphere by the midpoint rule in polar angle and azimuth.
+    fn pdf_integral(environment: &Environment) -> f64 {
+        let (rows, columns) = (400, 800);
+        let (d_polar, d_azimuth) = (PI / rows as f64, 2.0 * PI / columns as f64);
+        let mut integral = 0.0;
+        for j in 0..rows {
+            let polar = (j as f64 + 0.5) * d_polar;
+            for i in 0..columns {
+                let azimuth = (i as f64 + 0.5) * d_azimuth;
+                let direction = Vector::new(
+                    polar.sin() * azimuth.cos(),
+                    polar.cos(),
+                    polar.sin() * azimuth.sin(),
+                );
+                integral += environment.pdf_value(direction) * polar.sin() * d_polar * d_azimuth;
+            }
+        }
+        integral
+    }
+
+    #[test]
+    fn map_pdf_integrates_to_one() {
+        let path = std::env::temp_dir().join(format!("environment-{}.png", std::process::id()));
+        RgbImage::from_fn(8, 4, |x, y| Rgb([(x * 30) as u8, (y * 60) as u8, 255]))
+            .save(&path)
+            .unwrap();
+        let environment =
+            Environment::new_map(path.to_str().unwrap().to_string(), 30.0, 1.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let integral = pdf_integral(&environment);
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+    }
+
+    #[test]
+    fn sky_pdf_integrates_to_one() {
+        let environment = Environment::new_sky(30.0, 45.0, 3.0, 1.0);
+        let EnvironmentType::Sky {
+            sun, cos_sun_max, ..
+        } = environment.environment
+        else {
+            panic!("not a sky");
+        };
+        // about the sun, split at the edge of its disk, which is too small for a grid
+        // over the whole sphere.
+        let frame = ONB::new(sun);
+        let (steps, azimuths) = (200, 64);
+        let d_azimuth = 2.0 * PI / azimuths as f64;
+        let mut integral = 0.0;
+        for (low, high) in [(-1.0, cos_sun_max), (cos_sun_max, 1.0)] {
+            let d_cos = (high - low) / steps as f64;
+            for j in 0..steps {
+                let cos_theta = low + (j as f64 + 0.5) * d_cos;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                for i in 0..azimuths {
+                    let azimuth = (i as f64 + 0.5) * d_azimuth;
+                    let direction = frame.transform(Vector::new(
+                        sin_theta * azimuth.cos(),
+                        sin_theta * azimuth.sin(),
+                        cos_theta,
+                    ));
+                    integral += environment.pdf_value(direction) * d_cos * d_azimuth;
+                }
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::image::vector::Color;
+use image::RgbImage;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// first bytes of a checkpoint file, bumped whenever the layout changes.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT02";
+
+/// samples pixels in passes of `pass_samples` after a first pass of `min_samples`,
+/// until the standard error of a pixel's mean luminance falls below `tolerance` times
+/// the mean or it reaches the camera's samples per pixel.
+#[derive(Clone, Copy)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub pass_samples: u32,
+    pub tolerance: f64,
+    /// also saves `heatmap.png`, brighter where more samples were taken.
+    pub heatmap: bool,
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: u32, tolerance: f64) -> Self {
+        Self {
+            min_samples,
+            pass_samples: min_samples,
+            tolerance,
+            heatmap: false,
+        }
+    }
+}
+
+/// renders the whole frame `pass_samples` at a time up to the camera's samples per
+/// pixel, saving a preview after every pass, or at most once per `preview_interval`.
+#[derive(Clone, Copy)]
+pub struct Progressive {
+    pub pass_samples: u32,
+    pub preview_interval: Option<Duration>,
+}
+
+impl Progressive {
+    pub fn new(pass_samples: u32) -> Self {
+        Self {
+            pass_samples,
+            preview_interval: None,
+        }
+    }
+}
+
+/// saves the accumulation buffer to `path` between passes, at most once per
+/// `interval` and once at the end, so an interrupted render can be resumed.
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub interval: Duration,
+}
+
+impl Checkpoint {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+        }
+    }
+}
+
+/// what the samples in a checkpoint were drawn with. carrying on with anything else
+/// would mix two different sample patterns in the same pixels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RenderSettings {
+    pub seed: u64,
+    pub samples_per_pixel: u32,
+    /// `Sampler::fingerprint` of the sampler.
+    pub sampler: u64,
+    /// `Integrator::fingerprint` of the integrator.
+    pub integrator: u64,
+}
+
+impl RenderSettings {
+    /// the first setting other than the seed that differs from `other`.
+    pub fn mismatch(&self, other: &Self) -> Option<&'static str> {
+        if self.samples_per_pixel != other.samples_per_pixel {
+            Some("samples per pixel")
+        } else if self.sampler != other.sampler {
+            Some("sampler")
+        } else if self.integrator != other.integrator {
+            Some("integrator")
+        } else {
+            None
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&self.samples_per_pixel.to_le_bytes())?;
+        writer.write_all(&self.sampler.to_le_bytes())?;
+        writer.write_all(&self.integrator.to_le_bytes())
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        Ok(Self {
+            seed: read_u64(reader)?,
+            samples_per_pixel: read_u32(reader)?,
+            sampler: read_u64(reader)?,
+            integrator: read_u64(reader)?,
+        })
+    }
+}
+
+/// float accumulation buffer for a whole image.
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Pixel::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Pixel] {
+        &mut self.pixels
+    }
+
+    /// the mean of every pixel, ready to save.
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            self.pixels[(y * self.width + x) as usize]
+                .color()
+                .as_pixel()
+        })
+    }
+
+    /// writes the sums and sample counts along with the settings the samples were
+    /// drawn with, which together with the counts fix every random number still to
+    /// come. the file is replaced in one step so a crash never leaves half a checkpoint.
+    pub fn save_checkpoint(&self, path: &Path, settings: &RenderSettings) -> io::Result<()> {
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&self.width.to_le_bytes())?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        settings.write(&mut writer)?;
+        for pixel in &self.pixels {
+            pixel.write(&mut writer)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(temporary, path)
+    }
+
+    /// reads a film and its settings back from `save_checkpoint`.
+    pub fn load_checkpoint(path: &Path) -> io::Result<(Self, RenderSettings)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a checkpoint of this version", path.display()),
+            ));
+        }
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let settings = RenderSettings::read(&mut reader)?;
+        let pixels = (0..width as usize * height as usize)
+            .map(|_| Pixel::read(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok((
+            Self {
+                width,
+                height,
+                pixels,
+            },
+            settings,
+        ))
+    }
+}
+
+/// running sums of the samples taken in one pixel.
+#[derive(Clone, Copy, Default)]
+pub struct Pixel {
+    sum: Color,
+    luminance_sum: f64,
+    luminance_sum_squared: f64,
+    samples: u32,
+}
+
+impl Pixel {
+    pub fn add(&mut self, color: Color) {
+        let luminance = color.luminance();
+        self.sum = self.sum + color;
+        self.luminance_sum += luminance;
+        self.luminance_sum_squared += luminance * luminance;
+        self.samples += 1;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// mean of the samples so far, black before the first.
+    pub fn color(&self) -> Color {
+        if self.samples == 0 {
+            return Color::black();
+        }
+        self.sum / self.samples as f64
+    }
+
+    /// standard error of the mean luminance over the mean, zero for a pixel that has
+    /// only ever been black and infinite until there are two samples.
+    pub fn relative_error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = self.luminance_sum / n;
+        let variance =
+            ((self.luminance_sum_squared - mean * self.luminance_sum) / (n - 1.0)).max(0.0);
+        if variance == 0.0 {
+            return 0.0;
+        }
+        if mean <= 0.0 {
+            return f64::INFINITY;
+        }
+        (variance / n).sqrt() / mean
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        for value in [
+            self.sum.r,
+            self.sum.g,
+            self.sum.b,
+            self.luminance_sum,
+            self.luminance_sum_squared,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&self.samples.to_le_bytes())
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        Ok(Self {
+            sum: Color::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?),
+            luminance_sum: read_f64(reader)?,
+            luminance_sum_squared: read_f64(reader)?,
+            samples: read_u32(reader)?,
+        })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let mut film = Film::new(3, 2);
+        for (i, pixel) in film.pixels_mut().iter_mut().enumerate() {
+            for _ in 0..=i {
+                pixel.add(Color::new(i as f64, 0.5, 0.25));
+            }
+        }
+        let settings = RenderSettings {
+            seed: 7,
+            samples_per_pixel: 16,
+            sampler: 2,
+            integrator: 99,
+        };
+        let path = std::env::temp_dir().join(format!("film-{}.ckpt", std::process::id()));
+        film.save_checkpoint(&path, &settings).unwrap();
+        let (loaded, loaded_settings) = Film::load_checkpoint(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded_settings, settings);
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        for (a, b) in film.pixels().iter().zip(loaded.pixels()) {
+            assert_eq!(a.samples(), b.samples());
+            assert_eq!(a.relative_error().to_bits(), b.relative_error().to_bits());
+            let (a, b) = (a.color(), b.color());
+            assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+        }
+    }
+}
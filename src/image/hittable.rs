@@ -7,9 +7,10 @@ use std::f64::INFINITY;
 mod aabb;
 use aabb::AABB;
 pub mod bvh;
+pub mod lightsampler;
 pub mod material;
 
-use crate::image::util::{random, random_interval, Interval};
+use crate::image::util::{random, Interval};
 use material::texture::Texture;
 pub use material::{HitRecord, Material, ScatterRecord};
 use crate::image::hittable::material::onb::ONB;
@@ -48,6 +49,32 @@ struct Transform {
     pub cos_theta: f64,
 }
 
+impl Transform {
+    fn direction_to_local(&self, d: Vector) -> Vector {
+        Vector::new(
+            self.cos_theta * d.x - self.sin_theta * d.z,
+            d.y,
+            self.sin_theta * d.x + self.cos_theta * d.z,
+        )
+    }
+
+    fn direction_to_world(&self, d: Vector) -> Vector {
+        Vector::new(
+            self.cos_theta * d.x + self.sin_theta * d.z,
+            d.y,
+            -self.sin_theta * d.x + self.cos_theta * d.z,
+        )
+    }
+
+    fn to_local(&self, p: Vector) -> Vector {
+        self.direction_to_local(p - self.offset)
+    }
+
+    fn to_world(&self, p: Vector) -> Vector {
+        self.direction_to_world(p) + self.offset
+    }
+}
+
 impl Default for Transform {
     fn default() -> Self {
         Self {
@@ -70,6 +97,10 @@ pub struct Hittable {
     medium: ConstantMedium,
     is_medium: bool,
     material: Material,
+    /// cutout mask, luminance is the probability of a hit being kept.
+    opacity: Option<Texture>,
+    /// keeps an emissive object out of the automatically collected light list.
+    exclude_from_lights: bool,
     bbox: AABB,
 }
 
@@ -117,6 +148,32 @@ impl Hittable {
         self.medium.phase_function = Material::new_isotropic(Texture::new_solid(albedo));
         self.is_medium = true;
     }
+
+    pub fn set_opacity(&mut self, opacity: Texture) {
+        self.opacity = Some(opacity);
+    }
+
+    /// emissive objects are sampled as lights unless excluded here.
+    pub fn exclude_from_lights(&mut self) {
+        self.exclude_from_lights = true;
+    }
+
+    pub fn is_light(&self) -> bool {
+        !self.is_medium && !self.exclude_from_lights && self.material.is_emissive()
+    }
+
+    /// opacity of the surface at a point, one without a mask.
+    fn alpha(&self, u: f64, v: f64, p: Vector) -> f64 {
+        match &self.opacity {
+            Some(opacity) => opacity.value(u, v, p).luminance().clamp(0.0, 1.0),
+            None => 1.0,
+        }
+    }
+
+    /// whether a ray is stopped by a surface of opacity `alpha` rather than let through.
+    fn is_opaque(alpha: f64) -> bool {
+        alpha >= 1.0 || (alpha > 0.0 && random() < alpha)
+    }
     pub fn new_quad(q: Vector, u: Vector, v: Vector, material: Material) -> Self {
         let bbox_d1 = AABB::new_from_vector(q, q + u + v);
         let bbox_d2 = AABB::new_from_vector(q + u, q + v);
@@ -140,6 +197,8 @@ impl Hittable {
             transform: Default::default(),
             medium: Default::default(),
             is_medium: false,
+            opacity: None,
+            exclude_from_lights: false,
         }
     }
     pub fn new_sphere(center: Vector, radius: f64, material: Material) -> Self {
@@ -152,6 +211,8 @@ impl Hittable {
             transform: Default::default(),
             medium: Default::default(),
             is_medium: false,
+            opacity: None,
+            exclude_from_lights: false,
             material,
             bbox: AABB::new_from_vector(center - rvec, center + rvec),
         }
@@ -171,11 +232,19 @@ impl Hittable {
             transform: Default::default(),
             medium: Default::default(),
             is_medium: false,
+            opacity: None,
+            exclude_from_lights: false,
             material,
             bbox: AABB::new_from_aabb(&bbox1, &bbox2),
         }
     }
-    fn hit_sphere(&self, ray: &Ray, ray_t: util::Interval, rec: &mut HitRecord) -> bool {
+    fn hit_sphere(
+        &self,
+        ray: &Ray,
+        ray_t: util::Interval,
+        rec: &mut HitRecord,
+        cutout: bool,
+    ) -> bool {
         let HittableType::Sphere { center, radius } = self.hittable else {
             return false;
         };
@@ -189,33 +258,42 @@ impl Hittable {
             return false;
         }
         let d_sqrt = discriminant.sqrt();
-        let mut root = (h - d_sqrt) / a;
-        if !ray_t.surrounds(root) {
-            root = (h + d_sqrt) / a;
+        for root in [(h - d_sqrt) / a, (h + d_sqrt) / a] {
             if !ray_t.surrounds(root) {
-                return false;
+                continue;
+            }
+            let p = ray.at(root);
+            let outward_normal = (p - current_center) / radius;
+            let (mut u, mut v) = (0.0, 0.0);
+            self.get_sphere_uv(outward_normal, &mut u, &mut v);
+            let alpha = self.alpha(u, v, p);
+            if cutout && !Self::is_opaque(alpha) {
+                continue;
             }
+            rec.t = root;
+            rec.p = p;
+            rec.set_face_normal(*ray, outward_normal);
+            rec.u = u;
+            rec.v = v;
+            rec.footprint = ray.spread() * root * ray.direction().len() / (PI * radius);
+            rec.alpha = alpha;
+            rec.material = self.material.clone();
+            return true;
         }
-
-        rec.t = root;
-        rec.p = ray.at(rec.t);
-        let outward_normal = (rec.p - current_center) / radius;
-        rec.set_face_normal(*ray, outward_normal);
-        self.get_sphere_uv(outward_normal, &mut rec.u, &mut rec.v);
-        rec.material = self.material.clone();
-        true
+        false
     }
 
-    fn is_interior(a: f64, b: f64, rec: &mut HitRecord) -> bool {
+    fn is_interior(a: f64, b: f64) -> bool {
         let unit_interval = Interval::new(0.0, 1.0);
-        if !unit_interval.contains(a) || !unit_interval.contains(b) {
-            return false;
-        }
-        rec.u = a;
-        rec.v = b;
-        true
+        unit_interval.contains(a) && unit_interval.contains(b)
     }
-    fn hit_quad(&self, ray: &Ray, ray_t: util::Interval, rec: &mut HitRecord) -> bool {
+    fn hit_quad(
+        &self,
+        ray: &Ray,
+        ray_t: util::Interval,
+        rec: &mut HitRecord,
+        cutout: bool,
+    ) -> bool {
         let HittableType::Quad {
             q,
             u,
@@ -243,9 +321,17 @@ impl Hittable {
         let planar_hitpt_vector = intersection - q;
         let alpha = w.dot(planar_hitpt_vector.cross(v));
         let beta = w.dot(u.cross(planar_hitpt_vector));
-        if !Self::is_interior(alpha, beta, rec) {
+        if !Self::is_interior(alpha, beta) {
             return false;
         }
+        let opacity = self.alpha(alpha, beta, intersection);
+        if cutout && !Self::is_opaque(opacity) {
+            return false;
+        }
+        rec.alpha = opacity;
+        rec.u = alpha;
+        rec.v = beta;
+        rec.footprint = ray.spread() * t * ray.direction().len() / u.len().min(v.len());
         rec.t = t;
         rec.p = intersection;
         rec.material = self.material.clone();
@@ -254,83 +340,95 @@ impl Hittable {
     }
     pub fn hit(&self, ray: &Ray, ray_t: util::Interval, rec: &mut HitRecord) -> bool {
         if self.is_medium {
-            let mut rec1: HitRecord = Default::default();
-            let mut rec2: HitRecord = Default::default();
-            if !self.hit_object(ray, Interval::universe(), &mut rec1) {
-                return false;
-            }
-            if !self.hit_object(ray, Interval::new(rec1.t + 0.0001, INFINITY), &mut rec2) {
+            let Some((t_enter, t_exit)) = self.medium_span(ray, ray_t) else {
                 return false;
-            }
-
-            rec1.t = rec1.t.max(ray_t.min);
-            rec2.t = rec2.t.min(ray_t.max);
-            if rec1.t >= rec2.t {
-                return false;
-            }
-
-            rec1.t = rec1.t.max(0.);
+            };
 
             let ray_length = ray.direction().len();
-            let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
+            let distance_inside_boundary = (t_exit - t_enter) * ray_length;
             let hit_distance = self.medium.neg_inv_density * f64::log(random(), E);
 
             if hit_distance > distance_inside_boundary {
                 return false;
             }
 
-            rec.t = rec1.t + hit_distance / ray_length;
+            rec.t = t_enter + hit_distance / ray_length;
             rec.p = ray.at(rec.t);
             rec.normal = Vector::new(1., 0., 0.);
             rec.front_face = true;
+            rec.alpha = 1.0;
             rec.material = self.medium.phase_function.clone();
             true
         } else {
-            self.hit_object(ray, ray_t, rec)
+            self.hit_object(ray, ray_t, rec, true)
         }
     }
-    fn hit_object(&self, ray: &Ray, ray_t: util::Interval, rec: &mut HitRecord) -> bool {
-        let ray_transform = &Ray::new_time(
-            ray.origin() - self.transform.offset,
-            ray.direction(),
-            ray.time(),
-        );
-        let origin = Vector::new(
-            self.transform.cos_theta * ray_transform.origin().x
-                - self.transform.sin_theta * ray_transform.origin().z,
-            ray_transform.origin().y,
-            self.transform.sin_theta * ray_transform.origin().x
-                + self.transform.cos_theta * ray_transform.origin().z,
-        );
-        let direction = Vector::new(
-            self.transform.cos_theta * ray_transform.direction().x
-                - self.transform.sin_theta * ray_transform.direction().z,
-            ray_transform.direction().y,
-            self.transform.sin_theta * ray_transform.direction().x
-                + self.transform.cos_theta * ray_transform.direction().z,
-        );
-        let ray_rotated = &Ray::new_time(origin, direction, ray_transform.time());
+
+    /// hit on the surface itself, whatever its opacity mask, with the mask's alpha in
+    /// `rec.alpha`. never hits a medium. pdf and shadow queries go through here so that
+    /// they don't depend on the random cutouts of `hit`.
+    pub fn hit_shape(&self, ray: &Ray, ray_t: util::Interval, rec: &mut HitRecord) -> bool {
+        !self.is_medium && self.hit_object(ray, ray_t, rec, false)
+    }
+    /// fraction of light crossing the medium along `ray` within `ray_t`, one for surfaces.
+    pub fn transmittance(&self, ray: &Ray, ray_t: util::Interval) -> f64 {
+        if !self.is_medium {
+            return 1.0;
+        }
+        match self.medium_span(ray, ray_t) {
+            Some((t_enter, t_exit)) => {
+                let distance = (t_exit - t_enter) * ray.direction().len();
+                (distance / self.medium.neg_inv_density).exp()
+            }
+            None => 1.0,
+        }
+    }
+    /// where `ray` enters and leaves the medium's boundary, clipped to `ray_t`.
+    fn medium_span(&self, ray: &Ray, ray_t: util::Interval) -> Option<(f64, f64)> {
+        let mut rec1: HitRecord = Default::default();
+        let mut rec2: HitRecord = Default::default();
+        if !self.hit_object(ray, Interval::universe(), &mut rec1, false) {
+            return None;
+        }
+        if !self.hit_object(
+            ray,
+            Interval::new(rec1.t + 0.0001, INFINITY),
+            &mut rec2,
+            false,
+        ) {
+            return None;
+        }
+
+        let t_enter = rec1.t.max(ray_t.min);
+        let t_exit = rec2.t.min(ray_t.max);
+        if t_enter >= t_exit {
+            return None;
+        }
+        Some((t_enter.max(0.), t_exit))
+    }
+    /// intersection in the object's own frame, `cutout` deciding whether opacity masks
+    /// randomly let the ray through.
+    fn hit_object(
+        &self,
+        ray: &Ray,
+        ray_t: util::Interval,
+        rec: &mut HitRecord,
+        cutout: bool,
+    ) -> bool {
+        let origin = self.transform.to_local(ray.origin());
+        let direction = self.transform.direction_to_local(ray.direction());
+        let ray_rotated = &Ray::new_cone(origin, direction, ray.time(), ray.spread());
         let hit_object = match self.hittable {
-            HittableType::Sphere { .. } => self.hit_sphere(ray_rotated, ray_t, rec),
-            HittableType::Quad { .. } => self.hit_quad(ray_rotated, ray_t, rec),
+            HittableType::Sphere { .. } => self.hit_sphere(ray_rotated, ray_t, rec, cutout),
+            HittableType::Quad { .. } => self.hit_quad(ray_rotated, ray_t, rec, cutout),
         };
 
         if !hit_object {
             return false;
         }
 
-        rec.p = Vector::new(
-            self.transform.cos_theta * rec.p.x + self.transform.sin_theta * rec.p.z,
-            rec.p.y,
-            -self.transform.sin_theta * rec.p.x + self.transform.cos_theta * rec.p.z,
-        );
-        rec.p = rec.p + self.transform.offset;
-
-        rec.normal = Vector::new(
-            self.transform.cos_theta * rec.normal.x + self.transform.sin_theta * rec.normal.z,
-            rec.normal.y,
-            -self.transform.sin_theta * rec.normal.x + self.transform.cos_theta * rec.normal.z,
-        );
+        rec.p = self.transform.to_world(rec.p);
+        rec.normal = self.transform.direction_to_world(rec.normal);
 
         hit_object
     }
@@ -348,21 +446,58 @@ impl Hittable {
 
     pub fn random(&self, origin: Vector) -> Vector {
         match self.hittable {
-            HittableType::Quad { q, u, v, .. } => q + (random() * u) + (random() * v) - origin,
+            HittableType::Quad { q, u, v, .. } => {
+                self.transform.to_world(q + (random() * u) + (random() * v)) - origin
+            }
             HittableType::Sphere {center,radius,..} => {
-                let direction = center.at(0.0) - origin;
+                let direction = center.at(0.0) - self.transform.to_local(origin);
                 let dist_sqrd = direction.len_squared();
-                ONB::new(direction).transform(Vector::random_to_sphere(radius,dist_sqrd))
+                let local = ONB::new(direction).transform(Vector::random_to_sphere(radius,dist_sqrd));
+                self.transform.direction_to_world(local)
             },
             _ => Vector::new(1.0, 0.0, 0.0),
         }
     }
 
+    pub fn area(&self) -> f64 {
+        match self.hittable {
+            HittableType::Quad { area, .. } => area,
+            HittableType::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+        }
+    }
+
+    /// emitted power of a diffuse emitter, in luminance.
+    pub fn power(&self) -> f64 {
+        PI * self.area() * self.material.emission_estimate().luminance()
+    }
+
+    /// uniformly distributed point on the surface, facing out, with density 1 / area.
+    pub fn sample_surface(&self) -> HitRecord {
+        let mut rec = HitRecord::default();
+        let (p, normal) = match self.hittable {
+            HittableType::Quad { q, u, v, normal, .. } => {
+                rec.u = random();
+                rec.v = random();
+                (q + rec.u * u + rec.v * v, normal)
+            }
+            HittableType::Sphere { center, radius } => {
+                let normal = Vector::random_on_unit_sphere();
+                self.get_sphere_uv(normal, &mut rec.u, &mut rec.v);
+                (center.at(0.0) + radius * normal, normal)
+            }
+        };
+        rec.p = self.transform.to_world(p);
+        rec.normal = self.transform.direction_to_world(normal);
+        rec.front_face = true;
+        rec.material = self.material.clone();
+        rec
+    }
+
     pub fn pdf_value(&self, origin: Vector, direction: Vector) -> f64 {
         match self.hittable {
             HittableType::Quad { area, .. } => {
                 let mut hit_record: HitRecord = Default::default();
-                if !self.hit(
+                if !self.hit_shape(
                     &Ray::new(origin, direction),
                     Interval::new(0.001, INFINITY),
                     &mut hit_record,
@@ -375,14 +510,14 @@ impl Hittable {
             }
             HittableType::Sphere { center,radius,.. } => {
                 let mut hit_record: HitRecord = Default::default();
-                if !self.hit(
+                if !self.hit_shape(
                     &Ray::new(origin, direction),
                     Interval::new(0.001, INFINITY),
                     &mut hit_record,
                 ) {
                     return 0.0
                 }
-                let dist_squared = (center.at(0.0) - origin).len_squared();
+                let dist_squared = (center.at(0.0) - self.transform.to_local(origin)).len_squared();
                 let cos_theta_max = (1.0 - (radius * radius)/dist_squared).sqrt();
                 let solid_angle = 2.0* PI * (1.0 - cos_theta_max);
                 1.0 / solid_angle
@@ -410,6 +545,10 @@ impl HittableObjects {
         self.objects.push(hittable);
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
     pub fn add_hittables(&mut self, hittables: HittableObjects) {
         self.objects.extend(hittables.objects);
     }
@@ -439,6 +578,41 @@ impl HittableObjects {
         hit_something
     }
 
+    /// closest surface whatever its opacity, passing through media instead of
+    /// scattering in them.
+    pub fn hit_shape(&self, ray: &Ray, ray_t: util::Interval, rec: &mut HitRecord) -> bool {
+        let mut closest_so_far = ray_t.max;
+        let mut hit_something = false;
+        for object in self.objects.iter() {
+            if object.hit_shape(ray, util::Interval::new(ray_t.min, closest_so_far), rec) {
+                closest_so_far = rec.t;
+                hit_something = true;
+            }
+        }
+        hit_something
+    }
+
+    /// fraction of light getting past the surfaces along `ray` within `ray_t`, what
+    /// their opacity masks let through and zero behind a solid one.
+    pub fn surface_transmittance(&self, ray: &Ray, ray_t: util::Interval) -> f64 {
+        let mut transmittance = 1.0;
+        let mut interval = ray_t;
+        let mut rec = HitRecord::default();
+        while transmittance > 0.0 && self.hit_shape(ray, interval, &mut rec) {
+            transmittance *= 1.0 - rec.alpha;
+            interval = util::Interval::new(rec.t + 0.0001, ray_t.max);
+        }
+        transmittance
+    }
+
+    /// fraction of light surviving every medium along `ray` within `ray_t`.
+    pub fn transmittance(&self, ray: &Ray, ray_t: util::Interval) -> f64 {
+        self.objects
+            .iter()
+            .map(|object| object.transmittance(ray, ray_t))
+            .product()
+    }
+
     pub fn new_box(a: Vector, b: Vector, material: Material) -> Self {
         let mut sides = Self::new();
         let min = Vector::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
@@ -499,19 +673,67 @@ impl HittableObjects {
         }
     }
 
-    pub fn random(&self, origin: Vector) -> Vector {
-        if self.objects.is_empty() {
-            return Vector::new(1.0, 0.0, 0.0);
+    pub fn set_opacity(&mut self, opacity: Texture) {
+        for object in self.objects.iter_mut() {
+            object.set_opacity(opacity.clone());
         }
-        let i = random_interval(0., self.objects.len() as f64).floor() as usize;
-        self.objects[i].random(origin)
     }
 
-    pub fn pdf_value(&self, origin: Vector, direction: Vector) -> f64 {
-        if self.objects.is_empty() {
-            return 0.001;
+    pub fn exclude_from_lights(&mut self) {
+        for object in self.objects.iter_mut() {
+            object.exclude_from_lights();
         }
-        let i = random_interval(0., self.objects.len() as f64).floor() as usize;
-        self.objects[i].pdf_value(origin, direction)
+    }
+
+    /// every emissive object that has not been excluded from light sampling.
+    pub fn emissive(&self) -> Self {
+        Self {
+            objects: self
+                .objects
+                .iter()
+                .filter(|object| object.is_light())
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masked_light_pdf_is_deterministic() {
+        let light = Material::new_diffuse_light(Texture::new_solid(Color::white()));
+        let mut quad = Hittable::new_quad(
+            Vector::new(-1.0, 2.0, -1.0),
+            Vector::new(2.0, 0.0, 0.0),
+            Vector::new(0.0, 0.0, 2.0),
+            light,
+        );
+        quad.set_opacity(Texture::new_solid(Color::new(0.5, 0.5, 0.5)));
+        let origin = Vector::zero();
+        for _ in 0..100 {
+            let direction = quad.random(origin);
+            let pdf = quad.pdf_value(origin, direction);
+            assert!(pdf > 0.0);
+            assert_eq!(pdf, quad.pdf_value(origin, direction));
+        }
+    }
+
+    #[test]
+    fn masked_surface_transmits_one_minus_alpha() {
+        let mut objects = HittableObjects::new();
+        let mut quad = Hittable::new_quad(
+            Vector::new(-1.0, 1.0, -1.0),
+            Vector::new(2.0, 0.0, 0.0),
+            Vector::new(0.0, 0.0, 2.0),
+            Material::default(),
+        );
+        quad.set_opacity(Texture::new_solid(Color::new(0.25, 0.25, 0.25)));
+        objects.add(quad);
+        let ray = Ray::new(Vector::zero(), Vector::new(0.0, 1.0, 0.0));
+        let transmittance = objects.surface_transmittance(&ray, Interval::new(0.001, 2.0));
+        assert!((transmittance - 0.75).abs() < 1e-9);
     }
 }
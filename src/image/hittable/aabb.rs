@@ -13,13 +13,13 @@ impl AABB {
     fn pad_to_min(&mut self) {
         let delta = 0.0001;
         if self.x.size() < delta {
-            self.x.expand(delta);
+            self.x = self.x.expand(delta);
         }
         if self.y.size() < delta {
-            self.y.expand(delta);
+            self.y = self.y.expand(delta);
         }
         if self.z.size() < delta {
-            self.z.expand(delta);
+            self.z = self.z.expand(delta);
         }
     }
     pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
@@ -139,3 +139,16 @@ impl Add<Vector> for AABB {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_box_is_padded_and_hit() {
+        let aabb = AABB::new_from_vector(Vector::new(0.0, 1.0, 0.0), Vector::new(1.0, 1.0, 1.0));
+        assert!(aabb.y().size() > 0.0);
+        let ray = Ray::new(Vector::new(0.5, 0.0, 0.5), Vector::new(0.0, 1.0, 0.0));
+        assert!(aabb.hit(&ray, &mut Interval::new(0.001, f64::INFINITY)));
+    }
+}
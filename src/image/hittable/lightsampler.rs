@@ -0,0 +1,328 @@
+use crate::image::hittable::aabb::AABB;
+use crate::image::hittable::{HitRecord, Hittable, HittableObjects};
+use crate::image::ray::Ray;
+use crate::image::util::{random, Distribution1D, Interval};
+use crate::image::vector::Vector;
+use std::sync::Arc;
+
+/// above this many lights, selection goes through a light bvh.
+const BVH_LIGHT_COUNT: usize = 64;
+
+#[derive(Clone)]
+struct LightNode {
+    bbox: AABB,
+    power: f64,
+    left: usize,
+    right: usize,
+    light: Option<usize>,
+}
+
+enum LightSelection {
+    /// picks lights in proportion to their emitted power.
+    Power { distribution: Distribution1D },
+    /// picks lights by walking a bvh, weighing each subtree by power over squared
+    /// distance from the shading point.
+    Bvh {
+        nodes: Vec<LightNode>,
+        /// node indices and branch taken from the root to each light's leaf.
+        paths: Vec<Vec<(usize, bool)>>,
+    },
+}
+
+struct LightSamplerData {
+    lights: Vec<Hittable>,
+    selection: LightSelection,
+    /// power distribution independent of any shading point, for emitting from lights.
+    emission: Distribution1D,
+}
+
+/// samples directions towards the light list, `random` and `pdf_value` describe the
+/// same mixture over all lights.
+#[derive(Clone)]
+pub struct LightSampler {
+    data: Arc<LightSamplerData>,
+}
+
+impl LightSampler {
+    pub fn new(objects: &HittableObjects) -> Self {
+        if objects.objects.len() > BVH_LIGHT_COUNT {
+            Self::new_bvh(objects)
+        } else {
+            Self::new_power(objects)
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self::new_power(&HittableObjects::new())
+    }
+
+    pub fn new_power(objects: &HittableObjects) -> Self {
+        let lights = objects.objects.clone();
+        let distribution = Distribution1D::new(Self::weights(&lights));
+        Self {
+            data: Arc::new(LightSamplerData {
+                lights,
+                selection: LightSelection::Power {
+                    distribution: distribution.clone(),
+                },
+                emission: distribution,
+            }),
+        }
+    }
+
+    pub fn new_bvh(objects: &HittableObjects) -> Self {
+        let lights = objects.objects.clone();
+        let weights = Self::weights(&lights);
+        let mut nodes = Vec::new();
+        let mut indices = (0..lights.len()).collect::<Vec<_>>();
+        if !lights.is_empty() {
+            Self::build(&lights, &weights, &mut indices, &mut nodes);
+        }
+        let mut paths = vec![Vec::new(); lights.len()];
+        if !nodes.is_empty() {
+            Self::record_paths(&nodes, 0, &mut Vec::new(), &mut paths);
+        }
+        Self {
+            data: Arc::new(LightSamplerData {
+                lights,
+                selection: LightSelection::Bvh { nodes, paths },
+                emission: Distribution1D::new(weights),
+            }),
+        }
+    }
+
+    /// lights without a power estimate, like hand added non-emissive objects, get the
+    /// mean weight so they are still sampled.
+    fn weights(lights: &[Hittable]) -> Vec<f64> {
+        let powers = lights.iter().map(|l| l.power()).collect::<Vec<_>>();
+        let emitting = powers.iter().filter(|p| **p > 0.0).count();
+        let mean = if emitting > 0 {
+            powers.iter().sum::<f64>() / emitting as f64
+        } else {
+            1.0
+        };
+        powers
+            .into_iter()
+            .map(|p| if p > 0.0 { p } else { mean })
+            .collect()
+    }
+
+    fn build(
+        lights: &[Hittable],
+        weights: &[f64],
+        indices: &mut [usize],
+        nodes: &mut Vec<LightNode>,
+    ) -> usize {
+        let mut bbox = AABB::empty();
+        let mut centroids = AABB::empty();
+        let mut power = 0.0;
+        for &i in indices.iter() {
+            let light_box = lights[i].bounding_box();
+            bbox = AABB::new_from_aabb(&bbox, &light_box);
+            let center = Self::center(&light_box);
+            centroids = AABB::new_from_aabb(&centroids, &AABB::new_from_vector(center, center));
+            power += weights[i];
+        }
+        let index = nodes.len();
+        nodes.push(LightNode {
+            bbox,
+            power,
+            left: 0,
+            right: 0,
+            light: None,
+        });
+        if indices.len() == 1 {
+            nodes[index].light = Some(indices[0]);
+            return index;
+        }
+        let axis = centroids.longest_axis() as u64;
+        indices.sort_by(|a, b| {
+            let ca = Self::center(&lights[*a].bounding_box()).axis(axis);
+            let cb = Self::center(&lights[*b].bounding_box()).axis(axis);
+            ca.total_cmp(&cb)
+        });
+        let mid = indices.len() / 2;
+        let (left_indices, right_indices) = indices.split_at_mut(mid);
+        let left = Self::build(lights, weights, left_indices, nodes);
+        let right = Self::build(lights, weights, right_indices, nodes);
+        nodes[index].left = left;
+        nodes[index].right = right;
+        index
+    }
+
+    fn record_paths(
+        nodes: &[LightNode],
+        node: usize,
+        path: &mut Vec<(usize, bool)>,
+        paths: &mut [Vec<(usize, bool)>],
+    ) {
+        if let Some(light) = nodes[node].light {
+            paths[light] = path.clone();
+            return;
+        }
+        path.push((node, true));
+        Self::record_paths(nodes, nodes[node].left, path, paths);
+        path.pop();
+        path.push((node, false));
+        Self::record_paths(nodes, nodes[node].right, path, paths);
+        path.pop();
+    }
+
+    fn center(bbox: &AABB) -> Vector {
+        Vector::new(
+            0.5 * (bbox.x().min + bbox.x().max),
+            0.5 * (bbox.y().min + bbox.y().max),
+            0.5 * (bbox.z().min + bbox.z().max),
+        )
+    }
+
+    fn importance(node: &LightNode, p: Vector) -> f64 {
+        let center = Self::center(&node.bbox);
+        let half_diagonal = 0.5
+            * Vector::new(
+                node.bbox.x().size(),
+                node.bbox.y().size(),
+                node.bbox.z().size(),
+            )
+            .len();
+        let dist_squared = (p - center)
+            .len_squared()
+            .max(half_diagonal * half_diagonal);
+        node.power / dist_squared.max(1e-8)
+    }
+
+    /// probability of taking the left branch below `node` for a shading point at `p`.
+    fn left_probability(nodes: &[LightNode], node: usize, p: Vector) -> f64 {
+        let left = Self::importance(&nodes[nodes[node].left], p);
+        let right = Self::importance(&nodes[nodes[node].right], p);
+        if left + right <= 0.0 {
+            0.5
+        } else {
+            left / (left + right)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.lights.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.lights.len()
+    }
+
+    pub fn light(&self, i: usize) -> &Hittable {
+        &self.data.lights[i]
+    }
+
+    /// picks a light for a shading point at `p`, returning its index and probability.
+    pub fn select(&self, p: Vector) -> (usize, f64) {
+        match &self.data.selection {
+            LightSelection::Power { distribution } => distribution.sample_discrete(random()),
+            LightSelection::Bvh { nodes, .. } => {
+                let mut node = 0;
+                let mut pmf = 1.0;
+                let mut u = random();
+                while nodes[node].light.is_none() {
+                    let p_left = Self::left_probability(nodes, node, p);
+                    if u < p_left {
+                        u /= p_left;
+                        pmf *= p_left;
+                        node = nodes[node].left;
+                    } else {
+                        u = (u - p_left) / (1.0 - p_left);
+                        pmf *= 1.0 - p_left;
+                        node = nodes[node].right;
+                    }
+                }
+                (nodes[node].light.unwrap_or(0), pmf)
+            }
+        }
+    }
+
+    /// probability that `select` picks light `i` for a shading point at `p`.
+    pub fn selection_pdf(&self, i: usize, p: Vector) -> f64 {
+        match &self.data.selection {
+            LightSelection::Power { distribution } => distribution.discrete_pdf(i),
+            LightSelection::Bvh { nodes, paths } => {
+                paths[i].iter().fold(1.0, |pmf, &(node, left)| {
+                    let p_left = Self::left_probability(nodes, node, p);
+                    if left {
+                        pmf * p_left
+                    } else {
+                        pmf * (1.0 - p_left)
+                    }
+                })
+            }
+        }
+    }
+
+    /// picks a light to emit from by power, returning its index and probability.
+    pub fn sample_emitter(&self) -> (usize, f64) {
+        self.data.emission.sample_discrete(random())
+    }
+
+    /// area density of `sample_emitter` followed by `Hittable::sample_surface` producing
+    /// the point `t` along `ray`, zero if no light is there.
+    pub fn emission_pdf(&self, ray: &Ray, t: f64) -> f64 {
+        let tolerance = 1e-4 * t.abs() + 1e-6;
+        let interval = Interval::new(t - tolerance, t + tolerance);
+        let mut pdf = 0.0;
+        for (i, light) in self.data.lights.iter().enumerate() {
+            let mut rec = HitRecord::default();
+            if light.hit_shape(ray, interval, &mut rec) {
+                pdf += self.data.emission.discrete_pdf(i) / light.area();
+            }
+        }
+        pdf
+    }
+
+    pub fn random(&self, origin: Vector) -> Vector {
+        if self.is_empty() {
+            return Vector::new(1.0, 0.0, 0.0);
+        }
+        let (i, _) = self.select(origin);
+        self.data.lights[i].random(origin)
+    }
+
+    pub fn pdf_value(&self, origin: Vector, direction: Vector) -> f64 {
+        match &self.data.selection {
+            LightSelection::Power { .. } => {
+                let mut value = 0.0;
+                for (i, light) in self.data.lights.iter().enumerate() {
+                    let light_pdf = light.pdf_value(origin, direction);
+                    if light_pdf > 0.0 {
+                        value += self.selection_pdf(i, origin) * light_pdf;
+                    }
+                }
+                value
+            }
+            LightSelection::Bvh { nodes, .. } => {
+                if nodes.is_empty() {
+                    return 0.0;
+                }
+                let ray = Ray::new(origin, direction);
+                let mut value = 0.0;
+                let mut stack = vec![0];
+                while let Some(node) = stack.pop() {
+                    let mut interval = Interval::new(0.001, f64::INFINITY);
+                    if !nodes[node].bbox.hit(&ray, &mut interval) {
+                        continue;
+                    }
+                    match nodes[node].light {
+                        Some(i) => {
+                            let light_pdf = self.data.lights[i].pdf_value(origin, direction);
+                            if light_pdf > 0.0 {
+                                value += self.selection_pdf(i, origin) * light_pdf;
+                            }
+                        }
+                        None => {
+                            stack.push(nodes[node].left);
+                            stack.push(nodes[node].right);
+                        }
+                    }
+                }
+                value
+            }
+        }
+    }
+}
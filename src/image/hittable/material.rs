@@ -10,6 +10,16 @@ pub mod onb;
 pub mod pdf;
 pub mod texture;
 
+/// kind of scattering event, paths are limited per kind.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum BounceType {
+    #[default]
+    Diffuse,
+    Glossy,
+    Transmission,
+    Volume,
+}
+
 #[derive(Clone)]
 pub enum MaterialType {
     Lambertian { texture: Texture },
@@ -98,6 +108,29 @@ impl Material {
         }
     }
 
+    pub fn is_emissive(&self) -> bool {
+        matches!(self.material, MaterialType::DiffuseLight { .. })
+    }
+
+    /// emitted radiance at the middle of the surface, a cheap stand-in for the average.
+    pub fn emission_estimate(&self) -> Color {
+        match &self.material {
+            MaterialType::DiffuseLight { texture } => texture.value(0.5, 0.5, Vector::zero()),
+            _ => Color::black(),
+        }
+    }
+
+    /// surface color without lighting, for debug output.
+    pub fn albedo(&self, rec: &HitRecord) -> Color {
+        match &self.material {
+            MaterialType::Lambertian { texture }
+            | MaterialType::DiffuseLight { texture }
+            | MaterialType::Isotropic { texture } => texture.value_at(rec),
+            MaterialType::Metal { albedo, .. } => *albedo,
+            MaterialType::Dielectric { .. } => Color::white(),
+        }
+    }
+
     pub fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Color {
         match &self.material {
             MaterialType::DiffuseLight { .. } => self.emitted_diffuse_light(ray_in, rec),
@@ -112,7 +145,7 @@ impl Material {
         if !rec.front_face {
             return Color::black();
         }
-        texture.value(rec.u, rec.v, rec.p)
+        texture.value_at(rec)
     }
 
     fn scatter_isotropic(
@@ -126,6 +159,7 @@ impl Material {
         };
         // *ray_scattered = Ray::new_time(rec.p, Vector::random_unit_vector(), ray_in.time());
         scatter_record.attenuation = texture.value(rec.u, rec.v, rec.p);
+        scatter_record.bounce = BounceType::Volume;
         scatter_record.skip_pdf = false;
         scatter_record.pdf = PDF::new_sphere();
         true
@@ -142,9 +176,10 @@ impl Material {
         // let uvw = ONB::new(rec.normal);
         // let scatter_direction = uvw.transform(Vector::random_unit_vector());
         // *ray_scattered = Ray::new_time(rec.p, scatter_direction.unit_vector(), ray_in.time());
+        scatter_record.bounce = BounceType::Diffuse;
         scatter_record.skip_pdf=false;
         scatter_record.pdf = PDF::new_cosine(rec.normal);
-        scatter_record.attenuation = texture.value(rec.u, rec.v, rec.p);
+        scatter_record.attenuation = texture.value_at(rec);
         true
     }
     fn scatter_metal(
@@ -159,6 +194,7 @@ impl Material {
         let mut reflected = Vector::reflect(&(ray_in.direction()), rec.normal);
         reflected = reflected.unit_vector() + fuzz * Vector::random_unit_vector();
         scatter_record.attenuation = albedo;
+        scatter_record.bounce = BounceType::Glossy;
         scatter_record.skip_pdf = true;
         scatter_record.skip_pdf_ray = Ray::new_time(rec.p, reflected, ray_in.time());
         true
@@ -183,8 +219,10 @@ impl Material {
         let sin_theta = (1.0 - cos_theta.powf(2.0)).sqrt();
         let cannot_refract = ri * sin_theta > 1.0;
         let direction = if cannot_refract || Self::reflectance(cos_theta, ri) > util::random() {
+            scatter_record.bounce = BounceType::Glossy;
             unit_direction.reflect(rec.normal)
         } else {
+            scatter_record.bounce = BounceType::Transmission;
             unit_direction.refract(rec.normal, ri)
         };
         scatter_record.attenuation = Color::white();
@@ -208,12 +246,17 @@ pub struct HitRecord {
     pub material: Material,
     pub u: f64,
     pub v: f64,
+    /// width of the ray cone at the hit, in uv units.
+    pub footprint: f64,
+    /// opacity of the surface at the hit.
+    pub alpha: f64,
 }
 
 #[derive(Default)]
 pub struct ScatterRecord {
     pub attenuation: Color,
     pub pdf: PDF,
+    pub bounce: BounceType,
     pub skip_pdf: bool,
     pub skip_pdf_ray: Ray,
 }
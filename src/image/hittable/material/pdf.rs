@@ -1,5 +1,6 @@
+use crate::image::environment::Environment;
 use crate::image::hittable::material::onb::ONB;
-use crate::image::hittable::HittableObjects;
+use crate::image::hittable::lightsampler::LightSampler;
 use crate::image::util::random_interval;
 use crate::image::vector::Vector;
 use std::f64::consts::PI;
@@ -12,12 +13,15 @@ pub enum PDFType {
         uvw: ONB,
     },
     Lights {
-        objects: HittableObjects,
+        lights: LightSampler,
         origin: Vector,
     },
     MixPdfs {
         pdfs: Vec<PDF>
-    }
+    },
+    Environment {
+        environment: Environment,
+    },
 }
 
 #[derive(Default,Clone)]
@@ -52,17 +56,24 @@ impl PDF {
             _ => {}
         }
     }
-    pub fn new_lights(objects: &HittableObjects, origin: Vector) -> Self {
+    pub fn new_lights(lights: &LightSampler, origin: Vector) -> Self {
         Self::new(PDFType::Lights {
-            objects: objects.clone(),
+            lights: lights.clone(),
             origin,
         })
     }
 
+    pub fn new_environment(environment: &Environment) -> Self {
+        Self::new(PDFType::Environment {
+            environment: environment.clone(),
+        })
+    }
+
     pub fn value(&self, direction: Vector) -> f64 {
         match &self.pdf_type {
+            PDFType::Sphere => 1.0 / (4.0 * PI),
             PDFType::Cosine { uvw } => f64::max(direction.unit_vector().dot(uvw.w()) / PI, 0.0),
-            PDFType::Lights { objects, origin } => objects.pdf_value(*origin, direction),
+            PDFType::Lights { lights, origin } => lights.pdf_value(*origin, direction),
             PDFType::MixPdfs { pdfs } => {
                 let n = pdfs.len() as f64;
                 let frac = 1.0 / n;
@@ -72,18 +83,19 @@ impl PDF {
                 }
                 value
             }
-            _ => 0.001,
+            PDFType::Environment { environment } => environment.pdf_value(direction),
         }
     }
     pub fn generate(&self) -> Vector {
         match &self.pdf_type {
-            PDFType::Sphere => Vector::random_unit_vector(),
+            PDFType::Sphere => Vector::random_on_unit_sphere(),
             PDFType::Cosine { uvw } => uvw.transform(Vector::random_unit_vector()),
-            PDFType::Lights { objects, origin } => objects.random(*origin),
+            PDFType::Lights { lights, origin } => lights.random(*origin),
             PDFType::MixPdfs {pdfs} => {
                 let idx = random_interval(0., pdfs.len() as f64).floor() as usize;
                 pdfs[idx].generate()
             }
+            PDFType::Environment { environment } => environment.random(),
             _ => Vector::new(1.0,0.0,0.0)
         }
     }
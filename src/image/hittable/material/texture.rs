@@ -1,12 +1,117 @@
 mod imagedata;
-use imagedata::ImageData;
+pub use imagedata::{ImageData, TextureError};
 mod perlinnoise;
+mod worleynoise;
 use crate::image::hittable::material::texture::perlinnoise::PerlinNoise;
+use crate::image::hittable::material::texture::worleynoise::WorleyNoise;
+pub use worleynoise::WorleyFeature;
 use crate::image::hittable::material::texture::TextureType::{
     ImageTexture, NoiseTexture, SolidColor,
 };
+use crate::image::hittable::material::HitRecord;
 use crate::image::util;
 use crate::image::vector::{Color, Vector};
+use std::f64::consts::PI;
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+    #[default]
+    Nearest,
+    Bilinear,
+    Bicubic,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    #[default]
+    Clamp,
+}
+
+impl WrapMode {
+    fn wrap(&self, i: i64, n: u32) -> u32 {
+        let n = n as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m >= n {
+                    2 * n - 1 - m
+                } else {
+                    m
+                }
+            }
+            WrapMode::Clamp => i.clamp(0, n - 1),
+        };
+        wrapped as u32
+    }
+}
+
+/// rotation (degrees, about the uv origin) followed by scale and offset.
+#[derive(Clone, Copy)]
+pub struct UvTransform {
+    scale_u: f64,
+    scale_v: f64,
+    offset_u: f64,
+    offset_v: f64,
+    sin_theta: f64,
+    cos_theta: f64,
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self::new(1.0, 1.0, 0.0, 0.0, 0.0)
+    }
+}
+
+impl UvTransform {
+    pub fn new(scale_u: f64, scale_v: f64, offset_u: f64, offset_v: f64, rotation: f64) -> Self {
+        let radians = util::degree_to_radians(rotation);
+        Self {
+            scale_u,
+            scale_v,
+            offset_u,
+            offset_v,
+            sin_theta: radians.sin(),
+            cos_theta: radians.cos(),
+        }
+    }
+
+    fn apply(&self, u: f64, v: f64) -> (f64, f64) {
+        let ru = self.cos_theta * u - self.sin_theta * v;
+        let rv = self.sin_theta * u + self.cos_theta * v;
+        (
+            self.scale_u * ru + self.offset_u,
+            self.scale_v * rv + self.offset_v,
+        )
+    }
+
+    fn max_scale(&self) -> f64 {
+        self.scale_u.abs().max(self.scale_v.abs())
+    }
+}
+
+/// direction a gradient texture runs in, `t` goes from 0 at the start color to 1 at the end.
+#[derive(Clone, Copy)]
+pub enum GradientKind {
+    U,
+    V,
+    Linear { from: Vector, to: Vector },
+    Radial { center: Vector, radius: f64 },
+}
+
+/// shading inputs a texture graph is evaluated with.
+#[derive(Default, Clone, Copy)]
+struct TextureCoord {
+    u: f64,
+    v: f64,
+    p: Vector,
+    /// zero when the caller has no surface normal.
+    normal: Vector,
+    footprint: f64,
+}
+
 #[derive(Clone)]
 enum TextureType {
     SolidColor {
@@ -19,11 +124,83 @@ enum TextureType {
     },
     ImageTexture {
         image: ImageData,
+        filter: TextureFilter,
+        wrap: WrapMode,
+        uv_transform: UvTransform,
     },
     NoiseTexture {
         noise: PerlinNoise,
         scale: f64,
     },
+    RawNoiseTexture {
+        noise: PerlinNoise,
+        scale: f64,
+    },
+    FbmTexture {
+        noise: PerlinNoise,
+        scale: f64,
+        octaves: u32,
+        lacunarity: f64,
+        gain: f64,
+    },
+    TurbulenceTexture {
+        noise: PerlinNoise,
+        scale: f64,
+        octaves: u32,
+    },
+    WorleyTexture {
+        noise: WorleyNoise,
+        scale: f64,
+        feature: WorleyFeature,
+    },
+    WoodTexture {
+        noise: PerlinNoise,
+        scale: f64,
+        light: Color,
+        dark: Color,
+    },
+    UvCheckerTexture {
+        u_count: f64,
+        v_count: f64,
+        even: Color,
+        odd: Color,
+    },
+    GradientTexture {
+        start: Color,
+        end: Color,
+        kind: GradientKind,
+    },
+    MixTexture {
+        a: Box<Texture>,
+        b: Box<Texture>,
+        factor: Box<Texture>,
+    },
+    AddTexture {
+        a: Box<Texture>,
+        b: Box<Texture>,
+    },
+    MultiplyTexture {
+        a: Box<Texture>,
+        b: Box<Texture>,
+    },
+    ScaleBiasTexture {
+        input: Box<Texture>,
+        scale: f64,
+        bias: f64,
+    },
+    ColorRampTexture {
+        input: Box<Texture>,
+        stops: Vec<(f64, Color)>,
+    },
+    UvMapTexture {
+        input: Box<Texture>,
+        uv_transform: UvTransform,
+    },
+    TriplanarTexture {
+        input: Box<Texture>,
+        scale: f64,
+        sharpness: f64,
+    },
 }
 
 impl Default for TextureType {
@@ -63,23 +240,227 @@ impl Texture {
         })
     }
 
-    pub fn new_image(filename: String) -> Self {
-        let image_data = ImageData::new(filename);
-        Self::new(TextureType::ImageTexture { image: image_data })
+    pub fn new_image(filename: String) -> Result<Self, TextureError> {
+        Ok(Self::new_image_data(ImageData::new(filename)?))
+    }
+
+    /// for data textures (roughness, normals) whose values must not be gamma decoded.
+    pub fn new_image_linear(filename: String) -> Result<Self, TextureError> {
+        Ok(Self::new_image_data(ImageData::new_linear(filename)?))
+    }
+
+    pub fn new_image_alpha(filename: String) -> Result<Self, TextureError> {
+        Ok(Self::new_image_data(ImageData::new_alpha(filename)?))
+    }
+
+    /// loud placeholder for textures that failed to load.
+    pub fn new_missing() -> Self {
+        Self::new_uv_checker(16.0, 8.0, Color::magenta(), Color::black())
+    }
+
+    fn new_image_data(image: ImageData) -> Self {
+        Self::new(TextureType::ImageTexture {
+            image,
+            filter: TextureFilter::default(),
+            wrap: WrapMode::default(),
+            uv_transform: UvTransform::default(),
+        })
+    }
+
+    pub fn set_filter(&mut self, texture_filter: TextureFilter) {
+        if let ImageTexture { filter, .. } = &mut self.texture {
+            *filter = texture_filter;
+        }
+    }
+
+    pub fn set_wrap(&mut self, wrap_mode: WrapMode) {
+        if let ImageTexture { wrap, .. } = &mut self.texture {
+            *wrap = wrap_mode;
+        }
+    }
+
+    pub fn set_uv_transform(&mut self, transform: UvTransform) {
+        if let ImageTexture { uv_transform, .. } = &mut self.texture {
+            *uv_transform = transform;
+        }
+    }
+
+    pub fn generate_mipmaps(&mut self) {
+        if let ImageTexture { image, .. } = &mut self.texture {
+            image.generate_mipmaps();
+        }
     }
 
-    pub fn new_perlin(scale: f64) -> Self {
+    /// marble veins, `sin(scale * z + 10 * turbulence)`.
+    pub fn new_perlin(scale: f64, seed: u64) -> Self {
         Self::new(NoiseTexture {
-            noise: PerlinNoise::new(),
+            noise: PerlinNoise::new(seed),
+            scale,
+        })
+    }
+
+    pub fn new_noise(scale: f64, seed: u64) -> Self {
+        Self::new(TextureType::RawNoiseTexture {
+            noise: PerlinNoise::new(seed),
+            scale,
+        })
+    }
+
+    pub fn new_fbm(scale: f64, octaves: u32, lacunarity: f64, gain: f64, seed: u64) -> Self {
+        Self::new(TextureType::FbmTexture {
+            noise: PerlinNoise::new(seed),
+            scale,
+            octaves,
+            lacunarity,
+            gain,
+        })
+    }
+
+    pub fn new_turbulence(scale: f64, octaves: u32, seed: u64) -> Self {
+        Self::new(TextureType::TurbulenceTexture {
+            noise: PerlinNoise::new(seed),
+            scale,
+            octaves,
+        })
+    }
+
+    pub fn new_worley(scale: f64, feature: WorleyFeature, seed: u64) -> Self {
+        Self::new(TextureType::WorleyTexture {
+            noise: WorleyNoise::new(seed),
+            scale,
+            feature,
+        })
+    }
+
+    /// concentric rings around the y axis, perturbed by turbulence.
+    pub fn new_wood(scale: f64, light: Color, dark: Color, seed: u64) -> Self {
+        Self::new(TextureType::WoodTexture {
+            noise: PerlinNoise::new(seed),
+            scale,
+            light,
+            dark,
+        })
+    }
+
+    /// checker in surface uv space, with `u_count` by `v_count` squares.
+    pub fn new_uv_checker(u_count: f64, v_count: f64, even: Color, odd: Color) -> Self {
+        Self::new(TextureType::UvCheckerTexture {
+            u_count,
+            v_count,
+            even,
+            odd,
+        })
+    }
+
+    pub fn new_gradient(start: Color, end: Color, kind: GradientKind) -> Self {
+        Self::new(TextureType::GradientTexture { start, end, kind })
+    }
+
+    /// blends `a` into `b` by a constant factor in [0, 1].
+    pub fn new_mix(a: Texture, b: Texture, factor: f64) -> Self {
+        Self::new_mix_by(a, b, Texture::new_solid(Color::new(factor, factor, factor)))
+    }
+
+    /// blends `a` into `b` by the luminance of `factor`.
+    pub fn new_mix_by(a: Texture, b: Texture, factor: Texture) -> Self {
+        Self::new(TextureType::MixTexture {
+            a: Box::new(a),
+            b: Box::new(b),
+            factor: Box::new(factor),
+        })
+    }
+
+    pub fn new_add(a: Texture, b: Texture) -> Self {
+        Self::new(TextureType::AddTexture {
+            a: Box::new(a),
+            b: Box::new(b),
+        })
+    }
+
+    pub fn new_multiply(a: Texture, b: Texture) -> Self {
+        Self::new(TextureType::MultiplyTexture {
+            a: Box::new(a),
+            b: Box::new(b),
+        })
+    }
+
+    /// `input * scale + bias`, per channel.
+    pub fn new_scale_bias(input: Texture, scale: f64, bias: f64) -> Self {
+        Self::new(TextureType::ScaleBiasTexture {
+            input: Box::new(input),
+            scale,
+            bias,
+        })
+    }
+
+    /// maps the luminance of `input` through piecewise linear color stops.
+    pub fn new_color_ramp(input: Texture, mut stops: Vec<(f64, Color)>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self::new(TextureType::ColorRampTexture {
+            input: Box::new(input),
+            stops,
+        })
+    }
+
+    /// evaluates `input` with transformed surface uvs.
+    pub fn new_uv_map(input: Texture, uv_transform: UvTransform) -> Self {
+        Self::new(TextureType::UvMapTexture {
+            input: Box::new(input),
+            uv_transform,
+        })
+    }
+
+    /// projects `input` along the three world axes and blends by the surface normal,
+    /// for objects without usable uvs.
+    pub fn new_triplanar(input: Texture, scale: f64, sharpness: f64) -> Self {
+        Self::new(TextureType::TriplanarTexture {
+            input: Box::new(input),
             scale,
+            sharpness,
         })
     }
+
     pub fn value(&self, u: f64, v: f64, p: Vector) -> Color {
+        self.evaluate(TextureCoord {
+            u,
+            v,
+            p,
+            ..Default::default()
+        })
+    }
+
+    /// evaluates at a surface hit, using its normal and pixel footprint.
+    pub fn value_at(&self, rec: &HitRecord) -> Color {
+        self.evaluate(TextureCoord {
+            u: rec.u,
+            v: rec.v,
+            p: rec.p,
+            normal: rec.normal,
+            footprint: rec.footprint,
+        })
+    }
+
+    fn evaluate(&self, coord: TextureCoord) -> Color {
+        let TextureCoord { u, v, p, .. } = coord;
         match self.texture {
             SolidColor { color } => color,
             TextureType::CheckerTexture { .. } => self.value_checker_texture(u, v, p),
-            ImageTexture { .. } => self.value_image_texture(u, v, p),
+            ImageTexture { .. } => self.value_image_texture(u, v, coord.footprint),
             TextureType::NoiseTexture { .. } => self.value_noise_texture(u, v, p),
+            TextureType::RawNoiseTexture { .. }
+            | TextureType::FbmTexture { .. }
+            | TextureType::TurbulenceTexture { .. }
+            | TextureType::WorleyTexture { .. } => self.value_scalar_noise_texture(p),
+            TextureType::WoodTexture { .. } => self.value_wood_texture(p),
+            TextureType::UvCheckerTexture { .. } => self.value_uv_checker_texture(u, v),
+            TextureType::GradientTexture { .. } => self.value_gradient_texture(u, v, p),
+            TextureType::MixTexture { .. }
+            | TextureType::AddTexture { .. }
+            | TextureType::MultiplyTexture { .. }
+            | TextureType::ScaleBiasTexture { .. } => self.value_combine_texture(coord),
+            TextureType::ColorRampTexture { .. } => self.value_color_ramp_texture(coord),
+            TextureType::UvMapTexture { .. } => self.value_uv_map_texture(coord),
+            TextureType::TriplanarTexture { .. } => self.value_triplanar_texture(coord),
         }
     }
 
@@ -105,17 +486,92 @@ impl Texture {
         }
     }
 
-    fn value_image_texture(&self, u: f64, v: f64, _p: Vector) -> Color {
-        let ImageTexture { image } = &self.texture else {
+    fn value_image_texture(&self, u: f64, v: f64, footprint: f64) -> Color {
+        let ImageTexture {
+            image,
+            filter,
+            wrap,
+            uv_transform,
+        } = &self.texture
+        else {
             return Color::black();
         };
-        if image.height() <= 0 {
+        if image.height() == 0 {
             return Color::white();
         }
-        let clamp_interval = util::Interval::new(0.0, 1.0);
-        let i = (clamp_interval.clamp(u) * (image.width() as f64)) as u32;
-        let j = ((1.0 - clamp_interval.clamp(v)) * (image.height() as f64)) as u32;
-        image.pixel_data(i, j)
+        let (u, v) = uv_transform.apply(u, v);
+        let max_level = (image.levels() - 1) as f64;
+        let texel_footprint =
+            footprint * uv_transform.max_scale() * image.width().max(image.height()) as f64;
+        if max_level == 0.0 || texel_footprint <= 1.0 {
+            return Self::sample_level(image, 0, *filter, *wrap, u, v);
+        }
+        let level = texel_footprint.log2().min(max_level);
+        let lower = level.floor() as usize;
+        let upper = level.ceil() as usize;
+        let t = level - lower as f64;
+        let lower_color = Self::sample_level(image, lower, *filter, *wrap, u, v);
+        if upper == lower {
+            return lower_color;
+        }
+        let upper_color = Self::sample_level(image, upper, *filter, *wrap, u, v);
+        (1.0 - t) * lower_color + t * upper_color
+    }
+
+    fn sample_level(
+        image: &ImageData,
+        level: usize,
+        filter: TextureFilter,
+        wrap: WrapMode,
+        u: f64,
+        v: f64,
+    ) -> Color {
+        let width = image.level_width(level);
+        let height = image.level_height(level);
+        let texel = |i: i64, j: i64| {
+            image.level_pixel_data(level, wrap.wrap(i, width), wrap.wrap(j, height))
+        };
+        let x = u * width as f64;
+        let y = (1.0 - v) * height as f64;
+        match filter {
+            TextureFilter::Nearest => texel(x.floor() as i64, y.floor() as i64),
+            TextureFilter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (i, j) = (x.floor() as i64, y.floor() as i64);
+                let (tx, ty) = (x - x.floor(), y - y.floor());
+                let top = (1.0 - tx) * texel(i, j) + tx * texel(i + 1, j);
+                let bottom = (1.0 - tx) * texel(i, j + 1) + tx * texel(i + 1, j + 1);
+                (1.0 - ty) * top + ty * bottom
+            }
+            TextureFilter::Bicubic => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (i, j) = (x.floor() as i64, y.floor() as i64);
+                let wx = Self::catmull_rom_weights(x - x.floor());
+                let wy = Self::catmull_rom_weights(y - y.floor());
+                let mut color = Color::black();
+                for (dj, weight_y) in wy.iter().enumerate() {
+                    for (di, weight_x) in wx.iter().enumerate() {
+                        let sample = texel(i + di as i64 - 1, j + dj as i64 - 1);
+                        color = color + (weight_x * weight_y) * sample;
+                    }
+                }
+                // the negative lobes overshoot at sharp edges, which must not turn into
+                // negative or brighter than stored albedo.
+                let max = if image.is_float() { f64::INFINITY } else { 1.0 };
+                color.clamp(0.0, max)
+            }
+        }
+    }
+
+    fn catmull_rom_weights(t: f64) -> [f64; 4] {
+        let t2 = t * t;
+        let t3 = t2 * t;
+        [
+            0.5 * (-t3 + 2.0 * t2 - t),
+            0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+            0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+            0.5 * (t3 - t2),
+        ]
     }
 
     fn value_noise_texture(&self, _u: f64, _v: f64, p: Vector) -> Color {
@@ -124,4 +580,169 @@ impl Texture {
         };
         (1.0 + f64::sin(scale * p.z + 10.0 * noise.turb(p, 7))) * Color::new(0.5, 0.5, 0.5)
     }
+    fn value_scalar_noise_texture(&self, p: Vector) -> Color {
+        let value = match &self.texture {
+            TextureType::RawNoiseTexture { noise, scale } => 0.5 * (1.0 + noise.noise(*scale * p)),
+            TextureType::FbmTexture {
+                noise,
+                scale,
+                octaves,
+                lacunarity,
+                gain,
+            } => 0.5 * (1.0 + noise.fbm(*scale * p, *octaves, *lacunarity, *gain)),
+            TextureType::TurbulenceTexture {
+                noise,
+                scale,
+                octaves,
+            } => noise.turb(*scale * p, *octaves),
+            TextureType::WorleyTexture {
+                noise,
+                scale,
+                feature,
+            } => noise.value(*scale * p, *feature),
+            _ => 0.0,
+        };
+        let value = value.clamp(0.0, 1.0);
+        Color::new(value, value, value)
+    }
+
+    fn value_wood_texture(&self, p: Vector) -> Color {
+        let TextureType::WoodTexture {
+            noise,
+            scale,
+            light,
+            dark,
+        } = &self.texture
+        else {
+            return Color::black();
+        };
+        let q = *scale * p;
+        let radius = (q.x * q.x + q.z * q.z).sqrt() + 0.5 * noise.turb(q, 4);
+        let ring = radius - radius.floor();
+        let t = 0.5 * (1.0 - f64::cos(2.0 * PI * ring));
+        (1.0 - t) * *light + t * *dark
+    }
+
+    fn value_uv_checker_texture(&self, u: f64, v: f64) -> Color {
+        let TextureType::UvCheckerTexture {
+            u_count,
+            v_count,
+            even,
+            odd,
+        } = &self.texture
+        else {
+            return Color::black();
+        };
+        let ui = (u * u_count).floor() as i64;
+        let vi = (v * v_count).floor() as i64;
+        if (ui + vi) % 2 == 0 {
+            *even
+        } else {
+            *odd
+        }
+    }
+
+    fn value_gradient_texture(&self, u: f64, v: f64, p: Vector) -> Color {
+        let TextureType::GradientTexture { start, end, kind } = &self.texture else {
+            return Color::black();
+        };
+        let t = match kind {
+            GradientKind::U => u,
+            GradientKind::V => v,
+            GradientKind::Linear { from, to } => {
+                let axis = *to - *from;
+                (p - *from).dot(axis) / axis.len_squared()
+            }
+            GradientKind::Radial { center, radius } => (p - *center).len() / radius,
+        };
+        let t = t.clamp(0.0, 1.0);
+        (1.0 - t) * *start + t * *end
+    }
+    fn value_combine_texture(&self, coord: TextureCoord) -> Color {
+        match &self.texture {
+            TextureType::MixTexture { a, b, factor } => {
+                let t = factor.evaluate(coord).luminance().clamp(0.0, 1.0);
+                (1.0 - t) * a.evaluate(coord) + t * b.evaluate(coord)
+            }
+            TextureType::AddTexture { a, b } => a.evaluate(coord) + b.evaluate(coord),
+            TextureType::MultiplyTexture { a, b } => a.evaluate(coord) * b.evaluate(coord),
+            TextureType::ScaleBiasTexture { input, scale, bias } => {
+                *scale * input.evaluate(coord) + Color::new(*bias, *bias, *bias)
+            }
+            _ => Color::black(),
+        }
+    }
+
+    fn value_color_ramp_texture(&self, coord: TextureCoord) -> Color {
+        let TextureType::ColorRampTexture { input, stops } = &self.texture else {
+            return Color::black();
+        };
+        let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+            return Color::black();
+        };
+        let t = input.evaluate(coord).luminance();
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        for pair in stops.windows(2) {
+            let (t0, c0) = pair[0];
+            let (t1, c1) = pair[1];
+            if t <= t1 {
+                let width = t1 - t0;
+                if width <= 0.0 {
+                    return c1;
+                }
+                let s = (t - t0) / width;
+                return (1.0 - s) * c0 + s * c1;
+            }
+        }
+        last.1
+    }
+
+    fn value_uv_map_texture(&self, coord: TextureCoord) -> Color {
+        let TextureType::UvMapTexture {
+            input,
+            uv_transform,
+        } = &self.texture
+        else {
+            return Color::black();
+        };
+        let (u, v) = uv_transform.apply(coord.u, coord.v);
+        input.evaluate(TextureCoord { u, v, ..coord })
+    }
+
+    fn value_triplanar_texture(&self, coord: TextureCoord) -> Color {
+        let TextureType::TriplanarTexture {
+            input,
+            scale,
+            sharpness,
+        } = &self.texture
+        else {
+            return Color::black();
+        };
+        let q = *scale * coord.p;
+        let n = coord.normal;
+        let mut weights = [
+            n.x.abs().powf(*sharpness),
+            n.y.abs().powf(*sharpness),
+            n.z.abs().powf(*sharpness),
+        ];
+        let total: f64 = weights.iter().sum();
+        if n.near_zero() || total <= 0.0 {
+            weights = [1.0, 1.0, 1.0];
+        }
+        let total: f64 = weights.iter().sum();
+        let projections = [(q.z, q.y), (q.x, q.z), (q.x, q.y)];
+        let mut color = Color::black();
+        for (weight, (u, v)) in weights.iter().zip(projections) {
+            if *weight > 0.0 {
+                let sample = input.evaluate(TextureCoord { u, v, ..coord });
+                color = color + (*weight / total) * sample;
+            }
+        }
+        color
+    }
 }
@@ -1,23 +1,229 @@
 use crate::image::vector::Color;
-use image::{open, RgbImage};
+use image::imageops::{resize, FilterType};
+use image::{open, ColorType, DynamicImage, ImageBuffer, ImageError, Rgb, Rgb32FImage, RgbImage};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+#[derive(Debug)]
+pub struct TextureError {
+    path: String,
+    source: ImageError,
+}
+
+impl TextureError {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load texture '{}': {}", self.path, self.source)
+    }
+}
+
+impl Error for TextureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn load(filename: String) -> Result<DynamicImage, TextureError> {
+    open(&filename).map_err(|source| TextureError {
+        path: filename,
+        source,
+    })
+}
+
+/// how stored texel values map to linear color.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    /// color textures such as albedo maps, decoded from sRGB to linear.
+    #[default]
+    Srgb,
+    /// data textures such as roughness or normal maps, used as stored.
+    Linear,
+}
+
+/// texel storage keeps the precision of the source file.
+#[derive(Clone)]
+enum Texels {
+    Rgb8(RgbImage),
+    Rgb16(Rgb16Image),
+    Rgb32F(Rgb32FImage),
+}
+
+impl Texels {
+    fn from_dynamic(dynamic: DynamicImage) -> Self {
+        match dynamic.color() {
+            ColorType::Rgb32F | ColorType::Rgba32F => Texels::Rgb32F(dynamic.into_rgb32f()),
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+                Texels::Rgb16(dynamic.into_rgb16())
+            }
+            _ => Texels::Rgb8(dynamic.into_rgb8()),
+        }
+    }
+
+    fn width(&self) -> u32 {
+        match self {
+            Texels::Rgb8(data) => data.width(),
+            Texels::Rgb16(data) => data.width(),
+            Texels::Rgb32F(data) => data.width(),
+        }
+    }
+
+    fn height(&self) -> u32 {
+        match self {
+            Texels::Rgb8(data) => data.height(),
+            Texels::Rgb16(data) => data.height(),
+            Texels::Rgb32F(data) => data.height(),
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Texels::Rgb32F(_))
+    }
+
+    /// decoded to linear float, so that filtering averages light rather than encoded
+    /// values.
+    fn to_linear(&self, color_space: ColorSpace) -> Rgb32FImage {
+        if let Texels::Rgb32F(data) = self {
+            return data.clone();
+        }
+        Rgb32FImage::from_fn(self.width(), self.height(), |x, y| {
+            let color = self.get(x, y, color_space);
+            Rgb([color.r as f32, color.g as f32, color.b as f32])
+        })
+    }
+
+    /// stored values normalised to [0, 1], float data is returned as is.
+    fn get(&self, x: u32, y: u32, color_space: ColorSpace) -> Color {
+        match self {
+            Texels::Rgb8(data) => {
+                let rgb = data.get_pixel(x, y);
+                if color_space == ColorSpace::Srgb {
+                    let lut = srgb_lut();
+                    return Color::new(
+                        lut[rgb[0] as usize],
+                        lut[rgb[1] as usize],
+                        lut[rgb[2] as usize],
+                    );
+                }
+                Color::new(
+                    rgb[0] as f64 / 255.0,
+                    rgb[1] as f64 / 255.0,
+                    rgb[2] as f64 / 255.0,
+                )
+            }
+            Texels::Rgb16(data) => {
+                let rgb = data.get_pixel(x, y);
+                let decode = |c: u16| {
+                    let value = c as f64 / 65535.0;
+                    match color_space {
+                        ColorSpace::Srgb => srgb_to_linear(value),
+                        ColorSpace::Linear => value,
+                    }
+                };
+                Color::new(decode(rgb[0]), decode(rgb[1]), decode(rgb[2]))
+            }
+            Texels::Rgb32F(data) => {
+                let rgb = data.get_pixel(x, y);
+                Color::new(rgb[0] as f64, rgb[1] as f64, rgb[2] as f64)
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_lut() -> &'static [f64; 256] {
+    static LUT: OnceLock<[f64; 256]> = OnceLock::new();
+    LUT.get_or_init(|| {
+        let mut lut = [0.0; 256];
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = srgb_to_linear(i as f64 / 255.0);
+        }
+        lut
+    })
+}
 
 #[derive(Clone)]
 pub struct ImageData {
-    data: RgbImage,
+    /// level 0 is the full resolution image, each following level halves it.
+    levels: Arc<Vec<Texels>>,
     width: u32,
     height: u32,
+    color_space: ColorSpace,
 }
 
 impl ImageData {
-    pub fn new(filename: String) -> Self {
-        let data = open(filename).unwrap().into_rgb8();
-        let width = data.width();
-        let height = data.height();
+    /// 8 and 16 bit files are treated as sRGB, float formats (.hdr, .exr) as linear.
+    pub fn new(filename: String) -> Result<Self, TextureError> {
+        Self::new_with_color_space(filename, ColorSpace::Srgb)
+    }
+
+    pub fn new_linear(filename: String) -> Result<Self, TextureError> {
+        Self::new_with_color_space(filename, ColorSpace::Linear)
+    }
+
+    fn new_with_color_space(
+        filename: String,
+        color_space: ColorSpace,
+    ) -> Result<Self, TextureError> {
+        let texels = Texels::from_dynamic(load(filename)?);
+        let color_space = if texels.is_float() {
+            ColorSpace::Linear
+        } else {
+            color_space
+        };
+        Ok(Self::from_texels(texels, color_space))
+    }
+
+    /// loads only the alpha channel, replicated into rgb, for use as an opacity mask.
+    pub fn new_alpha(filename: String) -> Result<Self, TextureError> {
+        let rgba = load(filename)?.into_rgba8();
+        let data = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let alpha = rgba.get_pixel(x, y)[3];
+            Rgb([alpha, alpha, alpha])
+        });
+        Ok(Self::from_texels(Texels::Rgb8(data), ColorSpace::Linear))
+    }
+
+    fn from_texels(texels: Texels, color_space: ColorSpace) -> Self {
+        let width = texels.width();
+        let height = texels.height();
         Self {
-            data,
+            levels: Arc::new(vec![texels]),
             width,
             height,
+            color_space,
+        }
+    }
+
+    /// levels after the first are stored as linear float whatever the source format.
+    pub fn generate_mipmaps(&mut self) {
+        if self.levels.len() > 1 {
+            return;
         }
+        let mut levels = vec![self.levels[0].clone()];
+        let mut previous = self.levels[0].to_linear(self.color_space);
+        let (mut width, mut height) = (self.width, self.height);
+        while width > 1 || height > 1 {
+            width = (width / 2).max(1);
+            height = (height / 2).max(1);
+            previous = resize(&previous, width, height, FilterType::Triangle);
+            levels.push(Texels::Rgb32F(previous.clone()));
+        }
+        self.levels = Arc::new(levels);
     }
 
     pub fn width(&self) -> u32 {
@@ -28,15 +234,57 @@ impl ImageData {
         self.height
     }
 
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// whether the source holds float data, which may go above one.
+    pub fn is_float(&self) -> bool {
+        self.levels[0].is_float()
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn level_width(&self, level: usize) -> u32 {
+        self.levels[level].width()
+    }
+
+    pub fn level_height(&self, level: usize) -> u32 {
+        self.levels[level].height()
+    }
+
     pub fn pixel_data(&self, i: u32, j: u32) -> Color {
-        let x = i.min(self.width - 1);
-        let y = j.min(self.height - 1);
-
-        let rgb = self.data.get_pixel(x as u32, y as u32);
-        Color::new(
-            rgb[0] as f64 / 255.0,
-            rgb[1] as f64 / 255.0,
-            rgb[2] as f64 / 255.0,
-        )
+        self.level_pixel_data(0, i, j)
+    }
+
+    pub fn level_pixel_data(&self, level: usize, i: u32, j: u32) -> Color {
+        let data = &self.levels[level];
+        let x = i.min(data.width() - 1);
+        let y = j.min(data.height() - 1);
+        data.get(x, y, self.color_space)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mipmaps_average_srgb_in_linear_space() {
+        let checker = RgbImage::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let mut image = ImageData::from_texels(Texels::Rgb8(checker), ColorSpace::Srgb);
+        image.generate_mipmaps();
+        let top = image.level_pixel_data(image.levels() - 1, 0, 0);
+        for value in [top.r, top.g, top.b] {
+            assert!((value - 0.5).abs() < 1e-6, "{}", value);
+        }
     }
 }
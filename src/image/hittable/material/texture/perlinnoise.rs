@@ -1,5 +1,7 @@
 use crate::image::vector::Vector;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
 const POINT_COUNT: usize = 256;
 #[derive(Clone)]
@@ -11,15 +13,15 @@ pub struct PerlinNoise {
 }
 
 impl PerlinNoise {
-    fn permute(perm: &mut Vec<u32>) {
-        perm.shuffle(&mut rand::thread_rng());
+    fn permute(perm: &mut [u32], rng: &mut StdRng) {
+        perm.shuffle(rng);
     }
 
-    fn perlin_generate_perm(perm: &mut Vec<u32>) {
+    fn perlin_generate_perm(perm: &mut Vec<u32>, rng: &mut StdRng) {
         for i in 0..POINT_COUNT {
             perm.push(i as u32);
         }
-        Self::permute(perm);
+        Self::permute(perm, rng);
     }
 
     fn crossmult(a: f64, b: f64) -> f64 {
@@ -58,17 +60,23 @@ impl PerlinNoise {
         }
         accum
     }
-    pub fn new() -> Self {
+    /// the same seed always produces the same noise field.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut rand_float = Vec::new();
         for _ in (0..POINT_COUNT).step_by(1) {
-            rand_float.push(Vector::random_interval(-1.0, 1.0));
+            rand_float.push(Vector::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            ));
         }
         let mut perm_x = Vec::new();
         let mut perm_y = Vec::new();
         let mut perm_z = Vec::new();
-        Self::perlin_generate_perm(&mut perm_x);
-        Self::perlin_generate_perm(&mut perm_y);
-        Self::perlin_generate_perm(&mut perm_z);
+        Self::perlin_generate_perm(&mut perm_x, &mut rng);
+        Self::perlin_generate_perm(&mut perm_y, &mut rng);
+        Self::perlin_generate_perm(&mut perm_z, &mut rng);
         Self {
             rand_float,
             perm_x,
@@ -111,4 +119,22 @@ impl PerlinNoise {
         }
         accum.abs()
     }
+    /// fractional brownian motion, signed and roughly in [-1, 1].
+    pub fn fbm(&self, p: Vector, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut accum = 0.0;
+        let mut norm = 0.0;
+        let mut temp_p = p;
+        let mut weight = 1.0;
+        for _i in 0..octaves {
+            accum += weight * self.noise(temp_p);
+            norm += weight;
+            weight *= gain;
+            temp_p = temp_p * lacunarity;
+        }
+        if norm > 0.0 {
+            accum / norm
+        } else {
+            0.0
+        }
+    }
 }
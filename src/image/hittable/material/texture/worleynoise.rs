@@ -0,0 +1,74 @@
+use crate::image::vector::Vector;
+
+/// which distance of the cellular pattern a worley texture reports.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum WorleyFeature {
+    /// distance to the closest feature point, gives round cells.
+    #[default]
+    F1,
+    /// distance to the second closest feature point.
+    F2,
+    /// `F2 - F1`, dark along the borders between cells.
+    Edge,
+}
+
+/// cellular noise with one feature point per unit cell, hashed from the seed.
+#[derive(Clone)]
+pub struct WorleyNoise {
+    seed: u64,
+}
+
+impl WorleyNoise {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    fn hash(&self, i: i64, j: i64, k: i64, axis: u64) -> u64 {
+        let mut x = self.seed
+            ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (j as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+            ^ (k as u64).wrapping_mul(0x1656_67b1_9e37_79f9)
+            ^ axis.wrapping_mul(0x27d4_eb2f_1656_67c5);
+        // splitmix64 finaliser
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+
+    fn feature_point(&self, i: i64, j: i64, k: i64) -> Vector {
+        let unit = |axis: u64| (self.hash(i, j, k, axis) >> 11) as f64 / (1u64 << 53) as f64;
+        Vector::new(i as f64 + unit(0), j as f64 + unit(1), k as f64 + unit(2))
+    }
+
+    /// distances to the closest and second closest feature points.
+    pub fn distances(&self, p: Vector) -> (f64, f64) {
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let distance = (self.feature_point(i + di, j + dj, k + dk) - p).len();
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+
+    pub fn value(&self, p: Vector, feature: WorleyFeature) -> f64 {
+        let (f1, f2) = self.distances(p);
+        match feature {
+            WorleyFeature::F1 => f1,
+            WorleyFeature::F2 => f2,
+            WorleyFeature::Edge => f2 - f1,
+        }
+    }
+}
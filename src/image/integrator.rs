@@ -0,0 +1,558 @@
+use crate::image::environment::Environment;
+use crate::image::hittable::lightsampler::LightSampler;
+use crate::image::hittable::material::pdf::PDF;
+use crate::image::hittable::material::{BounceType, HitRecord, ScatterRecord};
+use crate::image::hittable::HittableObjects;
+use crate::image::light::Light;
+use crate::image::ray::Ray;
+use crate::image::sampler;
+use crate::image::util;
+use crate::image::vector::{Color, Vector};
+use crate::image::Camera;
+
+mod bdpt;
+mod mlt;
+mod photonmap;
+
+/// the parts of the scene light transport is computed from.
+pub struct Scene<'a> {
+    pub world: &'a HittableObjects,
+    pub environment: &'a Environment,
+    pub lights: &'a LightSampler,
+    pub delta_lights: &'a [Light],
+}
+
+/// path length limits, `total` caps every bounce and the others cap one kind each.
+/// russian roulette starts terminating low throughput paths after `roulette_depth`.
+#[derive(Clone, Copy)]
+pub struct BounceLimits {
+    pub total: u32,
+    pub diffuse: u32,
+    pub glossy: u32,
+    pub transmission: u32,
+    pub volume: u32,
+    pub roulette_depth: u32,
+}
+
+impl BounceLimits {
+    pub fn new(total: u32) -> Self {
+        Self {
+            total,
+            diffuse: total,
+            glossy: total,
+            transmission: total,
+            volume: total,
+            roulette_depth: 3,
+        }
+    }
+
+    fn words(&self) -> [u64; 6] {
+        [
+            self.total,
+            self.diffuse,
+            self.glossy,
+            self.transmission,
+            self.volume,
+            self.roulette_depth,
+        ]
+        .map(u64::from)
+    }
+}
+
+#[derive(Default)]
+struct BounceCounts {
+    diffuse: u32,
+    glossy: u32,
+    transmission: u32,
+    volume: u32,
+}
+
+impl BounceCounts {
+    /// counts a bounce, false when it would go over its limit.
+    fn add(&mut self, bounce: BounceType, limits: &BounceLimits) -> bool {
+        let (count, limit) = match bounce {
+            BounceType::Diffuse => (&mut self.diffuse, limits.diffuse),
+            BounceType::Glossy => (&mut self.glossy, limits.glossy),
+            BounceType::Transmission => (&mut self.transmission, limits.transmission),
+            BounceType::Volume => (&mut self.volume, limits.volume),
+        };
+        if *count >= limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+#[derive(Clone)]
+pub enum IntegratorType {
+    Path {
+        limits: BounceLimits,
+    },
+    /// camera and light subpaths joined at every pair of vertices.
+    Bidirectional {
+        max_depth: u32,
+    },
+    /// progressive photon mapping, `photons` shot per pass with gather radius
+    /// starting at `radius`.
+    PhotonMapping {
+        photons: usize,
+        radius: f64,
+        max_depth: u32,
+    },
+    /// primary sample space metropolis over the path tracer, see `mlt::render`.
+    Metropolis {
+        limits: BounceLimits,
+        bootstrap: usize,
+        chains: usize,
+        sigma: f64,
+        large_step_probability: f64,
+    },
+    /// emission plus one bounce of light, following specular chains up to `max_depth`.
+    DirectLighting {
+        max_depth: u32,
+    },
+    /// fraction of the cosine weighted hemisphere left open within `distance`.
+    AmbientOcclusion {
+        distance: f64,
+    },
+    Normals,
+    Albedo,
+}
+
+#[derive(Clone)]
+pub struct Integrator {
+    integrator: IntegratorType,
+}
+
+impl Integrator {
+    pub fn new_path(limits: BounceLimits) -> Self {
+        Self {
+            integrator: IntegratorType::Path { limits },
+        }
+    }
+
+    pub fn new_bidirectional(max_depth: u32) -> Self {
+        Self {
+            integrator: IntegratorType::Bidirectional { max_depth },
+        }
+    }
+
+    pub fn new_photon_mapping(photons: usize, radius: f64, max_depth: u32) -> Self {
+        Self {
+            integrator: IntegratorType::PhotonMapping {
+                photons,
+                radius,
+                max_depth,
+            },
+        }
+    }
+
+    pub fn new_metropolis(
+        limits: BounceLimits,
+        bootstrap: usize,
+        chains: usize,
+        sigma: f64,
+        large_step_probability: f64,
+    ) -> Self {
+        Self {
+            integrator: IntegratorType::Metropolis {
+                limits,
+                bootstrap,
+                chains,
+                sigma,
+                large_step_probability,
+            },
+        }
+    }
+
+    pub fn new_direct_lighting(max_depth: u32) -> Self {
+        Self {
+            integrator: IntegratorType::DirectLighting { max_depth },
+        }
+    }
+
+    pub fn new_ambient_occlusion(distance: f64) -> Self {
+        Self {
+            integrator: IntegratorType::AmbientOcclusion { distance },
+        }
+    }
+
+    pub fn new_normals() -> Self {
+        Self {
+            integrator: IntegratorType::Normals,
+        }
+    }
+
+    pub fn new_albedo() -> Self {
+        Self {
+            integrator: IntegratorType::Albedo,
+        }
+    }
+
+    /// identifies the algorithm and its settings in checkpoints, the same across runs
+    /// and builds.
+    pub fn fingerprint(&self) -> u64 {
+        let mut words = Vec::new();
+        match &self.integrator {
+            IntegratorType::Path { limits } => {
+                words.push(0);
+                words.extend(limits.words());
+            }
+            IntegratorType::Bidirectional { max_depth } => words.extend([1, *max_depth as u64]),
+            IntegratorType::PhotonMapping {
+                photons,
+                radius,
+                max_depth,
+            } => words.extend([2, *photons as u64, radius.to_bits(), *max_depth as u64]),
+            IntegratorType::Metropolis {
+                limits,
+                bootstrap,
+                chains,
+                sigma,
+                large_step_probability,
+            } => {
+                words.extend([
+                    3,
+                    *bootstrap as u64,
+                    *chains as u64,
+                    sigma.to_bits(),
+                    large_step_probability.to_bits(),
+                ]);
+                words.extend(limits.words());
+            }
+            IntegratorType::DirectLighting { max_depth } => words.extend([4, *max_depth as u64]),
+            IntegratorType::AmbientOcclusion { distance } => words.extend([5, distance.to_bits()]),
+            IntegratorType::Normals => words.push(6),
+            IntegratorType::Albedo => words.push(7),
+        }
+        words
+            .into_iter()
+            .fold(0, |hash, word| sampler::mix(hash ^ word))
+    }
+
+    /// radiance arriving along `ray`.
+    pub fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        match self.integrator {
+            IntegratorType::Path { .. } => self.li_path(ray, scene),
+            IntegratorType::Bidirectional { max_depth } => bdpt::li(ray, scene, max_depth),
+            // works on whole images, see `render_image`.
+            IntegratorType::PhotonMapping { .. } | IntegratorType::Metropolis { .. } => {
+                Color::black()
+            }
+            IntegratorType::DirectLighting { .. } => self.li_direct_lighting(ray, scene),
+            IntegratorType::AmbientOcclusion { .. } => self.li_ambient_occlusion(ray, scene),
+            IntegratorType::Normals => Self::li_normals(ray, scene),
+            IntegratorType::Albedo => Self::li_albedo(ray, scene),
+        }
+    }
+
+    /// renders every pixel at once, for integrators that share work between pixels.
+    /// `None` when the integrator works one ray at a time through `li`.
+    pub fn render_image(
+        &self,
+        camera: &Camera,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+    ) -> Option<Vec<Color>> {
+        match self.integrator {
+            IntegratorType::PhotonMapping {
+                photons,
+                radius,
+                max_depth,
+            } => Some(photonmap::render(
+                camera, scene, width, height, photons, radius, max_depth,
+            )),
+            IntegratorType::Metropolis { .. } => {
+                Some(mlt::render(&self.integrator, camera, scene, width, height))
+            }
+            _ => None,
+        }
+    }
+
+    /// iterative path tracer, carrying the path throughput from bounce to bounce.
+    fn li_path(&self, ray: &Ray, scene: &Scene) -> Color {
+        let IntegratorType::Path { limits } = &self.integrator else {
+            return Color::black();
+        };
+        let mut ray = *ray;
+        let mut throughput = Color::white();
+        let mut radiance = Color::black();
+        // density the previous vertex sampled `ray` with, `None` for camera rays and
+        // specular bounces, whose emission light sampling could not have found.
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut bounces = BounceCounts::default();
+
+        for depth in 0..limits.total {
+            let mut rec = HitRecord::default();
+            if !scene.world.hit(&ray, Self::interval(), &mut rec) {
+                let weight = Self::emission_weight(bsdf_pdf, scene, &ray);
+                radiance =
+                    radiance + weight * throughput * scene.environment.value(ray.direction());
+                break;
+            }
+            let emission = rec.material.emitted(&ray, &rec);
+            if emission.luminance() > 0.0 {
+                let weight = Self::emission_weight(bsdf_pdf, scene, &ray);
+                radiance = radiance + weight * throughput * emission;
+            }
+            let mut scatter_record = ScatterRecord::default();
+            if !rec.material.scatter(&ray, &rec, &mut scatter_record) {
+                break;
+            }
+
+            if scatter_record.skip_pdf {
+                if !bounces.add(scatter_record.bounce, limits) {
+                    break;
+                }
+                throughput = throughput * scatter_record.attenuation;
+                ray = scatter_record.skip_pdf_ray;
+                bsdf_pdf = None;
+            } else {
+                radiance =
+                    radiance + throughput * Self::direct_light(&ray, &rec, &scatter_record, scene);
+
+                if !bounces.add(scatter_record.bounce, limits) {
+                    break;
+                }
+                let ray_scattered = Ray::new_time(rec.p, scatter_record.pdf.generate(), ray.time());
+                let pdf = scatter_record.pdf.value(ray_scattered.direction());
+                let scattering_pdf = rec.material.scattering_pdf(&ray, &rec, &ray_scattered);
+                if pdf <= 0.0 || scattering_pdf <= 0.0 {
+                    break;
+                }
+                throughput = (scattering_pdf / pdf) * throughput * scatter_record.attenuation;
+                ray = ray_scattered;
+                bsdf_pdf = Some(pdf);
+            }
+
+            if depth + 1 >= limits.roulette_depth {
+                let survival = throughput.max_component().min(1.0);
+                if survival <= 0.0 || util::random() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+        radiance
+    }
+
+    /// light sampling at the first diffuse vertex, combined with one bsdf sample
+    /// that only counts what it hits directly.
+    fn li_direct_lighting(&self, ray: &Ray, scene: &Scene) -> Color {
+        let IntegratorType::DirectLighting { max_depth } = self.integrator else {
+            return Color::black();
+        };
+        let mut ray = *ray;
+        let mut throughput = Color::white();
+        let mut radiance = Color::black();
+        for _ in 0..max_depth {
+            let mut rec = HitRecord::default();
+            if !scene.world.hit(&ray, Self::interval(), &mut rec) {
+                return radiance + throughput * scene.environment.value(ray.direction());
+            }
+            radiance = radiance + throughput * rec.material.emitted(&ray, &rec);
+            let mut scatter_record = ScatterRecord::default();
+            if !rec.material.scatter(&ray, &rec, &mut scatter_record) {
+                return radiance;
+            }
+            if scatter_record.skip_pdf {
+                throughput = throughput * scatter_record.attenuation;
+                ray = scatter_record.skip_pdf_ray;
+                continue;
+            }
+            return radiance
+                + throughput
+                    * (Self::direct_light(&ray, &rec, &scatter_record, scene)
+                        + Self::bsdf_light_color(&ray, &rec, &scatter_record, scene));
+        }
+        radiance
+    }
+
+    fn li_ambient_occlusion(&self, ray: &Ray, scene: &Scene) -> Color {
+        let IntegratorType::AmbientOcclusion { distance } = self.integrator else {
+            return Color::black();
+        };
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(ray, Self::interval(), &mut rec) {
+            return Color::white();
+        }
+        let direction = PDF::new_cosine(rec.normal).generate();
+        let occlusion_ray = Ray::new_time(rec.p, direction, ray.time());
+        let mut occluder = HitRecord::default();
+        let interval = util::Interval::new(0.001, distance / direction.len());
+        if scene.world.hit(&occlusion_ray, interval, &mut occluder) {
+            Color::black()
+        } else {
+            Color::white()
+        }
+    }
+
+    fn li_normals(ray: &Ray, scene: &Scene) -> Color {
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(ray, Self::interval(), &mut rec) {
+            return Color::black();
+        }
+        let normal = rec.normal.unit_vector();
+        Color::new(
+            0.5 * (normal.x + 1.0),
+            0.5 * (normal.y + 1.0),
+            0.5 * (normal.z + 1.0),
+        )
+    }
+
+    fn li_albedo(ray: &Ray, scene: &Scene) -> Color {
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(ray, Self::interval(), &mut rec) {
+            return scene.environment.value(ray.direction());
+        }
+        rec.material.albedo(&rec)
+    }
+
+    fn interval() -> util::Interval {
+        util::Interval::new(0.001, f64::INFINITY)
+    }
+
+    /// next event estimation towards the light list, the environment and the delta lights.
+    fn direct_light(
+        ray: &Ray,
+        rec: &HitRecord,
+        scatter_record: &ScatterRecord,
+        scene: &Scene,
+    ) -> Color {
+        let mut color = Self::delta_light_color(ray, rec, scatter_record.attenuation, scene);
+        if let Some(light_pdf) = Self::light_pdf(scene, rec.p) {
+            color = color + Self::light_sample_color(ray, rec, scatter_record, scene, &light_pdf);
+        }
+        color
+    }
+
+    /// one bsdf sample that only counts what it hits directly, the counterpart of
+    /// `direct_light` under MIS.
+    fn bsdf_light_color(
+        ray: &Ray,
+        rec: &HitRecord,
+        scatter_record: &ScatterRecord,
+        scene: &Scene,
+    ) -> Color {
+        let ray_scattered = Ray::new_time(rec.p, scatter_record.pdf.generate(), ray.time());
+        let pdf = scatter_record.pdf.value(ray_scattered.direction());
+        let scattering_pdf = rec.material.scattering_pdf(ray, rec, &ray_scattered);
+        if pdf <= 0.0 || scattering_pdf <= 0.0 {
+            return Color::black();
+        }
+        let emission = Self::shadow_radiance(&ray_scattered, scene);
+        let weight = Self::emission_weight(Some(pdf), scene, &ray_scattered);
+        (weight * scattering_pdf / pdf) * scatter_record.attenuation * emission
+    }
+
+    /// the light sampling strategy, an even mix of the light list and the environment.
+    fn light_pdf(scene: &Scene, origin: Vector) -> Option<PDF> {
+        if scene.lights.is_empty() && !scene.environment.is_sampled() {
+            return None;
+        }
+        let mut light_pdf = PDF::new_mix();
+        if !scene.lights.is_empty() {
+            light_pdf.add_to_mix(PDF::new_lights(scene.lights, origin));
+        }
+        if scene.environment.is_sampled() {
+            light_pdf.add_to_mix(PDF::new_environment(scene.environment));
+        }
+        Some(light_pdf)
+    }
+
+    /// MIS weight for radiance found by bsdf sampling `ray`.
+    fn emission_weight(bsdf_pdf: Option<f64>, scene: &Scene, ray: &Ray) -> f64 {
+        let Some(bsdf_pdf) = bsdf_pdf else {
+            return 1.0;
+        };
+        match Self::light_pdf(scene, ray.origin()) {
+            Some(light_pdf) => util::power_heuristic(bsdf_pdf, light_pdf.value(ray.direction())),
+            None => 1.0,
+        }
+    }
+
+    /// a shadow ray towards a sampled light weighted against the chance of bsdf
+    /// sampling finding the same direction.
+    fn light_sample_color(
+        ray: &Ray,
+        rec: &HitRecord,
+        scatter_record: &ScatterRecord,
+        scene: &Scene,
+        light_pdf: &PDF,
+    ) -> Color {
+        let shadow_ray = Ray::new_time(rec.p, light_pdf.generate(), ray.time());
+        let pdf = light_pdf.value(shadow_ray.direction());
+        if pdf <= 0.0 {
+            return Color::black();
+        }
+        let scattering_pdf = rec.material.scattering_pdf(ray, rec, &shadow_ray);
+        if scattering_pdf <= 0.0 {
+            return Color::black();
+        }
+        let radiance = Self::shadow_radiance(&shadow_ray, scene);
+        let bsdf_pdf = scatter_record.pdf.value(shadow_ray.direction());
+        let weight = util::power_heuristic(pdf, bsdf_pdf);
+        (weight * scattering_pdf / pdf) * scatter_record.attenuation * radiance
+    }
+
+    /// delta lights can't be hit by scattered rays, so each one is connected to
+    /// the shading point with a shadow ray.
+    fn delta_light_color(ray: &Ray, rec: &HitRecord, attenuation: Color, scene: &Scene) -> Color {
+        let mut color = Color::black();
+        for light in scene.delta_lights {
+            let sample = light.sample(rec.p);
+            let shadow_ray = Ray::new_time(rec.p, sample.direction, ray.time());
+            let scattering_pdf = rec.material.scattering_pdf(ray, rec, &shadow_ray);
+            if scattering_pdf <= 0.0 {
+                continue;
+            }
+            let visibility = Self::visibility(&shadow_ray, sample.distance, scene);
+            color = color + (visibility * scattering_pdf) * attenuation * sample.radiance;
+        }
+        color
+    }
+
+    /// light arriving along a shadow ray from the surfaces it reaches or from the
+    /// environment, attenuated by the media it crosses rather than stopped by them.
+    /// cutout surfaces add their emission weighted by alpha and pass on the rest, so the
+    /// result is the expectation of what stochastic cutouts would give.
+    fn shadow_radiance(ray: &Ray, scene: &Scene) -> Color {
+        let mut radiance = Color::black();
+        let mut surface_transmittance = 1.0;
+        let mut interval = Self::interval();
+        let mut rec = HitRecord::default();
+        while surface_transmittance > 0.0 {
+            if !scene.world.hit_shape(ray, interval, &mut rec) {
+                let environment = scene.environment.value(ray.direction());
+                if environment.luminance() > 0.0 {
+                    let transmittance = scene.world.transmittance(ray, Self::interval());
+                    radiance = radiance + (surface_transmittance * transmittance) * environment;
+                }
+                break;
+            }
+            let emitted = rec.material.emitted(ray, &rec);
+            if emitted.luminance() > 0.0 {
+                let transmittance = scene
+                    .world
+                    .transmittance(ray, util::Interval::new(0.001, rec.t));
+                radiance = radiance + (surface_transmittance * rec.alpha * transmittance) * emitted;
+            }
+            surface_transmittance *= 1.0 - rec.alpha;
+            interval = util::Interval::new(rec.t + 0.0001, f64::INFINITY);
+        }
+        radiance
+    }
+
+    /// fraction of light reaching the origin of `ray` from `distance` along it, zero
+    /// when a solid surface is in the way.
+    fn visibility(ray: &Ray, distance: f64, scene: &Scene) -> f64 {
+        let interval = util::Interval::new(0.001, distance - 0.001);
+        let surface_transmittance = scene.world.surface_transmittance(ray, interval);
+        if surface_transmittance <= 0.0 {
+            return 0.0;
+        }
+        surface_transmittance * scene.world.transmittance(ray, interval)
+    }
+}
@@ -0,0 +1,471 @@
+use crate::image::hittable::material::pdf::PDF;
+use crate::image::hittable::material::{BounceType, HitRecord, ScatterRecord};
+use crate::image::integrator::{Integrator, Scene};
+use crate::image::ray::Ray;
+use crate::image::util;
+use crate::image::vector::{Color, Vector};
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+    Medium,
+}
+
+/// a point on a camera or light subpath, densities are per unit area.
+struct Vertex {
+    kind: VertexKind,
+    rec: HitRecord,
+    ray_in: Ray,
+    /// throughput from the start of the subpath up to this vertex.
+    beta: Color,
+    attenuation: Color,
+    pdf: PDF,
+    scatters: bool,
+    delta: bool,
+    /// radiance leaving towards the previous vertex.
+    emission: Color,
+    /// density of light sampling choosing this point, for emitters on the camera path.
+    emission_pdf: f64,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn new_camera(ray: &Ray) -> Self {
+        let rec = HitRecord {
+            p: ray.origin(),
+            ..Default::default()
+        };
+        Self::new(VertexKind::Camera, rec, *ray, Color::white())
+    }
+
+    fn new_light(rec: HitRecord, emission: Color, pdf_fwd: f64) -> Self {
+        let mut vertex = Self::new(VertexKind::Light, rec, Ray::default(), emission / pdf_fwd);
+        vertex.emission = emission;
+        vertex.pdf_fwd = pdf_fwd;
+        vertex
+    }
+
+    fn new(kind: VertexKind, rec: HitRecord, ray_in: Ray, beta: Color) -> Self {
+        Self {
+            kind,
+            rec,
+            ray_in,
+            beta,
+            attenuation: Color::black(),
+            pdf: PDF::default(),
+            scatters: false,
+            delta: false,
+            emission: Color::black(),
+            emission_pdf: 0.0,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn p(&self) -> Vector {
+        self.rec.p
+    }
+
+    /// scattering towards `direction`, including the cosine at surfaces.
+    fn f(&self, direction: Vector) -> Color {
+        if !self.scatters || self.delta {
+            return Color::black();
+        }
+        let scattered = Ray::new_time(self.p(), direction, self.ray_in.time());
+        let scattering_pdf = self
+            .rec
+            .material
+            .scattering_pdf(&self.ray_in, &self.rec, &scattered);
+        scattering_pdf * self.attenuation
+    }
+
+    /// throughput carried past this vertex along `ray`.
+    fn beta_out(&self, ray: &Ray) -> Color {
+        if self.kind == VertexKind::Camera {
+            return self.beta;
+        }
+        if self.delta {
+            return self.beta * self.attenuation;
+        }
+        let pdf = self.pdf.value(ray.direction());
+        if pdf <= 0.0 {
+            return Color::black();
+        }
+        (1.0 / pdf) * self.beta * self.f(ray.direction())
+    }
+
+    /// density of this vertex sampling `next`.
+    fn pdf(&self, next: &Vertex) -> f64 {
+        let direction = next.p() - self.p();
+        let pdf = match self.kind {
+            VertexKind::Camera => 0.0,
+            VertexKind::Light => return self.pdf_light(next),
+            VertexKind::Surface | VertexKind::Medium if self.delta => 0.0,
+            VertexKind::Surface | VertexKind::Medium => self.pdf.value(direction),
+        };
+        next.convert_density(pdf, self.p())
+    }
+
+    /// density of an emitter at this vertex sending its light towards `next`.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let direction = (next.p() - self.p()).unit_vector();
+        let pdf = f64::max(0.0, self.rec.normal.dot(direction)) / PI;
+        next.convert_density(pdf, self.p())
+    }
+
+    /// turns a solid angle density seen from `from` into an area density here.
+    fn convert_density(&self, pdf: f64, from: Vector) -> f64 {
+        let w = self.p() - from;
+        let dist_squared = w.len_squared();
+        if dist_squared == 0.0 {
+            return 0.0;
+        }
+        let cosine = match self.kind {
+            VertexKind::Surface | VertexKind::Light => self.rec.normal.dot(w).abs() / w.len(),
+            VertexKind::Camera | VertexKind::Medium => 1.0,
+        };
+        pdf * cosine / dist_squared
+    }
+}
+
+/// bidirectional path tracing, with every connection of a camera and a light subpath
+/// weighted by the power heuristic. connections through the lens are not made, and the
+/// environment and delta lights are only reached from the camera side.
+pub fn li(ray: &Ray, scene: &Scene, max_depth: u32) -> Color {
+    let max_depth = max_depth as usize;
+    let mut camera_path = vec![Vertex::new_camera(ray)];
+    let escaped = random_walk(
+        scene,
+        *ray,
+        Color::white(),
+        1.0,
+        max_depth + 1,
+        &mut camera_path,
+    );
+    let light_path = light_subpath(scene, ray.time(), max_depth);
+
+    let mut radiance = Color::black();
+    if let Some(escaped) = escaped {
+        radiance = radiance + escaped_color(&camera_path, &escaped, scene);
+    }
+    for t in 2..=camera_path.len() {
+        let vertex = &camera_path[t - 1];
+        if vertex.scatters && !vertex.delta {
+            radiance = radiance + vertex.beta * camera_side_lights(vertex, scene);
+        }
+        // light sampling makes its own light vertex, so s = 1 needs no light subpath.
+        for s in 0..=light_path.len().max(1) {
+            if s + t - 1 > max_depth {
+                break;
+            }
+            radiance = radiance + connect(scene, &camera_path, &light_path, s, t);
+        }
+    }
+    radiance
+}
+
+fn light_subpath(scene: &Scene, time: f64, max_depth: usize) -> Vec<Vertex> {
+    let mut path = Vec::new();
+    if scene.lights.is_empty() || max_depth < 3 {
+        return path;
+    }
+    let (i, pmf) = scene.lights.sample_emitter();
+    let light = scene.lights.light(i);
+    let rec = light.sample_surface();
+    let emission = rec.material.emitted(&Ray::default(), &rec);
+    let pdf_origin = pmf / light.area();
+    if pdf_origin <= 0.0 || emission.luminance() <= 0.0 {
+        return path;
+    }
+    let emission_pdf = PDF::new_cosine(rec.normal);
+    let direction = emission_pdf.generate();
+    let pdf_direction = emission_pdf.value(direction);
+    if pdf_direction <= 0.0 {
+        return path;
+    }
+    let cosine = rec.normal.dot(direction.unit_vector()).abs();
+    let beta = (cosine / (pdf_origin * pdf_direction)) * emission;
+    let ray = Ray::new_time(rec.p, direction, time);
+    path.push(Vertex::new_light(rec, emission, pdf_origin));
+    random_walk(scene, ray, beta, pdf_direction, max_depth - 1, &mut path);
+    path
+}
+
+/// extends `path` by sampling scattered directions until it has `max_vertices`,
+/// returning the ray that left the scene if it escaped.
+fn random_walk(
+    scene: &Scene,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf_fwd: f64,
+    max_vertices: usize,
+    path: &mut Vec<Vertex>,
+) -> Option<Ray> {
+    let interval = util::Interval::new(0.001, f64::INFINITY);
+    while path.len() < max_vertices {
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(&ray, interval, &mut rec) {
+            return Some(ray);
+        }
+        let mut scatter_record = ScatterRecord::default();
+        let scatters = rec.material.scatter(&ray, &rec, &mut scatter_record);
+        let kind = if scatters && scatter_record.bounce == BounceType::Volume {
+            VertexKind::Medium
+        } else {
+            VertexKind::Surface
+        };
+        let emission = rec.material.emitted(&ray, &rec);
+        let emission_pdf = if emission.luminance() > 0.0 {
+            scene.lights.emission_pdf(&ray, rec.t)
+        } else {
+            0.0
+        };
+        let mut vertex = Vertex::new(kind, rec, ray, beta);
+        vertex.emission = emission;
+        vertex.emission_pdf = emission_pdf;
+        vertex.scatters = scatters;
+        vertex.delta = scatters && scatter_record.skip_pdf;
+        vertex.attenuation = scatter_record.attenuation;
+        vertex.pdf = scatter_record.pdf;
+        if let Some(prev) = path.last() {
+            vertex.pdf_fwd = vertex.convert_density(pdf_fwd, prev.p());
+        }
+        if !scatters {
+            path.push(vertex);
+            return None;
+        }
+
+        let pdf_rev;
+        let next = if vertex.delta {
+            beta = beta * vertex.attenuation;
+            pdf_fwd = 0.0;
+            pdf_rev = 0.0;
+            scatter_record.skip_pdf_ray
+        } else {
+            let next = Ray::new_time(vertex.p(), vertex.pdf.generate(), ray.time());
+            let pdf = vertex.pdf.value(next.direction());
+            let scattering_pdf = vertex.rec.material.scattering_pdf(&ray, &vertex.rec, &next);
+            if pdf <= 0.0 || scattering_pdf <= 0.0 {
+                path.push(vertex);
+                return None;
+            }
+            beta = (scattering_pdf / pdf) * beta * vertex.attenuation;
+            pdf_fwd = pdf;
+            pdf_rev = vertex.pdf.value(-1.0 * ray.direction());
+            next
+        };
+        if let Some(prev) = path.last_mut() {
+            prev.pdf_rev = prev.convert_density(pdf_rev, vertex.p());
+        }
+        path.push(vertex);
+        ray = next;
+    }
+    None
+}
+
+/// contribution of the strategy using `s` light and `t` camera vertices.
+fn connect(
+    scene: &Scene,
+    camera_path: &[Vertex],
+    light_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> Color {
+    let pt = &camera_path[t - 1];
+    match s {
+        0 => {
+            if pt.emission.luminance() <= 0.0 {
+                return Color::black();
+            }
+            let weight = mis_weight(camera_path, light_path, None, s, t);
+            weight * pt.beta * pt.emission
+        }
+        1 => {
+            if !pt.scatters || pt.delta || scene.lights.is_empty() {
+                return Color::black();
+            }
+            let direction = scene.lights.random(pt.p());
+            let pdf = scene.lights.pdf_value(pt.p(), direction);
+            if pdf <= 0.0 {
+                return Color::black();
+            }
+            let f = pt.f(direction);
+            if f.luminance() <= 0.0 {
+                return Color::black();
+            }
+            let ray = Ray::new_time(pt.p(), direction, pt.ray_in.time());
+            let mut rec = HitRecord::default();
+            if !scene
+                .world
+                .hit(&ray, util::Interval::new(0.001, f64::INFINITY), &mut rec)
+            {
+                return Color::black();
+            }
+            let emission = rec.material.emitted(&ray, &rec);
+            if emission.luminance() <= 0.0 {
+                return Color::black();
+            }
+            let pdf_origin = scene.lights.emission_pdf(&ray, rec.t);
+            let sampled = Vertex::new_light(rec, emission, pdf_origin);
+            let weight = mis_weight(camera_path, light_path, Some(&sampled), s, t);
+            (weight / pdf) * pt.beta * f * emission
+        }
+        _ => {
+            let qs = &light_path[s - 1];
+            if !pt.scatters || pt.delta || !qs.scatters || qs.delta {
+                return Color::black();
+            }
+            let d = qs.p() - pt.p();
+            let distance = d.len();
+            let contribution =
+                (1.0 / (distance * distance)) * qs.beta * qs.f(-1.0 * d) * pt.f(d) * pt.beta;
+            if contribution.luminance() <= 0.0 {
+                return Color::black();
+            }
+            let shadow_ray = Ray::new_time(pt.p(), d / distance, pt.ray_in.time());
+            let visibility = Integrator::visibility(&shadow_ray, distance, scene);
+            if visibility <= 0.0 {
+                return Color::black();
+            }
+            (visibility * mis_weight(camera_path, light_path, None, s, t)) * contribution
+        }
+    }
+}
+
+/// power heuristic over all strategies that could have built the same path, found by
+/// walking the ratios of reverse to forward densities out from the connection.
+fn mis_weight(
+    camera_path: &[Vertex],
+    light_path: &[Vertex],
+    sampled: Option<&Vertex>,
+    s: usize,
+    t: usize,
+) -> f64 {
+    if s + t == 2 {
+        return 1.0;
+    }
+    // (pdf_fwd, pdf_rev, delta) of each vertex, with the connection applied.
+    let mut camera = camera_path[..t]
+        .iter()
+        .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+        .collect::<Vec<_>>();
+    // a light sampled vertex stands in for the whole light subpath.
+    let mut light = light_path[..s - usize::from(sampled.is_some())]
+        .iter()
+        .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+        .collect::<Vec<_>>();
+    let qs = match sampled {
+        Some(sampled) => {
+            light.push((sampled.pdf_fwd, sampled.pdf_rev, false));
+            Some(sampled)
+        }
+        None if s > 0 => Some(&light_path[s - 1]),
+        None => None,
+    };
+    let pt = &camera_path[t - 1];
+    let pt_minus = &camera_path[t - 2];
+
+    camera[t - 1].2 = false;
+    if let Some(qs) = qs {
+        light[s - 1].2 = false;
+        camera[t - 1].1 = qs.pdf(pt);
+        camera[t - 2].1 = reverse_pdf(pt, qs, pt_minus);
+        light[s - 1].1 = pt.pdf(qs);
+        if s > 1 {
+            light[s - 2].1 = reverse_pdf(qs, pt, &light_path[s - 2]);
+        }
+    } else {
+        camera[t - 1].1 = pt.emission_pdf;
+        camera[t - 2].1 = pt.pdf_light(pt_minus);
+    }
+
+    let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+    let mut sum = 0.0;
+    let mut ri = 1.0;
+    for i in (2..t).rev() {
+        ri *= remap(camera[i].1) / remap(camera[i].0);
+        if !camera[i].2 && !camera[i - 1].2 {
+            sum += ri * ri;
+        }
+    }
+    ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap(light[i].1) / remap(light[i].0);
+        let delta_before = i > 0 && light[i - 1].2;
+        if !light[i].2 && !delta_before {
+            sum += ri * ri;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+/// density of `vertex` sampling `next` once it is reached from `from` instead.
+fn reverse_pdf(vertex: &Vertex, from: &Vertex, next: &Vertex) -> f64 {
+    match vertex.kind {
+        VertexKind::Light => vertex.pdf_light(next),
+        _ if vertex.delta => 0.0,
+        _ => {
+            let incoming = vertex.p() - from.p();
+            let pdf = match vertex.kind {
+                VertexKind::Medium => PDF::new_sphere(),
+                _ => PDF::new_cosine(face_normal(vertex.rec.normal, incoming)),
+            };
+            next.convert_density(pdf.value(next.p() - vertex.p()), vertex.p())
+        }
+    }
+}
+
+/// the normal flipped to the side `incoming` arrives from.
+fn face_normal(normal: Vector, incoming: Vector) -> Vector {
+    if normal.dot(incoming) > 0.0 {
+        -1.0 * normal
+    } else {
+        normal
+    }
+}
+
+/// environment light found by a camera path leaving the scene, weighted against
+/// sampling the environment directly.
+fn escaped_color(camera_path: &[Vertex], escaped: &Ray, scene: &Scene) -> Color {
+    let Some(last) = camera_path.last() else {
+        return Color::black();
+    };
+    let mut weight = 1.0;
+    if last.kind != VertexKind::Camera && !last.delta && scene.environment.is_sampled() {
+        let bsdf_pdf = last.pdf.value(escaped.direction());
+        weight = util::power_heuristic(bsdf_pdf, scene.environment.pdf_value(escaped.direction()));
+    }
+    weight * last.beta_out(escaped) * scene.environment.value(escaped.direction())
+}
+
+/// the environment and delta lights, which light subpaths never start from.
+fn camera_side_lights(vertex: &Vertex, scene: &Scene) -> Color {
+    let time = vertex.ray_in.time();
+    let mut color = Color::black();
+    if scene.environment.is_sampled() {
+        let direction = scene.environment.random();
+        let pdf = scene.environment.pdf_value(direction);
+        let f = vertex.f(direction);
+        let shadow_ray = Ray::new_time(vertex.p(), direction, time);
+        if pdf > 0.0 && f.luminance() > 0.0 {
+            let visibility = Integrator::visibility(&shadow_ray, f64::INFINITY, scene);
+            let weight = util::power_heuristic(pdf, vertex.pdf.value(direction));
+            color = color + (visibility * weight / pdf) * f * scene.environment.value(direction);
+        }
+    }
+    for light in scene.delta_lights {
+        let sample = light.sample(vertex.p());
+        let f = vertex.f(sample.direction);
+        if f.luminance() <= 0.0 {
+            continue;
+        }
+        let shadow_ray = Ray::new_time(vertex.p(), sample.direction, time);
+        let visibility = Integrator::visibility(&shadow_ray, sample.distance, scene);
+        color = color + visibility * f * sample.radiance;
+    }
+    color
+}
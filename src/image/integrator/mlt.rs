@@ -0,0 +1,123 @@
+use crate::image::integrator::{Integrator, IntegratorType, Scene};
+use crate::image::sampler::{self, PrimarySamples};
+use crate::image::util::{self, Distribution1D};
+use crate::image::vector::Color;
+use crate::image::Camera;
+use indicatif::ProgressBar;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+/// images the chains are splatted into in parallel.
+const BATCHES: usize = 16;
+
+/// primary sample space metropolis over the path tracer. `bootstrap` independent paths
+/// estimate the image brightness and pick where the chains start, then the chains make
+/// `spp` mutations per pixel between them, splatting every proposal by its acceptance.
+pub fn render(
+    integrator: &IntegratorType,
+    camera: &Camera,
+    scene: &Scene,
+    width: u32,
+    height: u32,
+) -> Vec<Color> {
+    let IntegratorType::Metropolis {
+        limits,
+        bootstrap,
+        chains,
+        sigma,
+        large_step_probability,
+    } = *integrator
+    else {
+        return Vec::new();
+    };
+    let path = Integrator::new_path(limits);
+    let pixel_count = (width * height) as usize;
+    let evaluate = || {
+        let x = util::random() * width as f64;
+        let y = util::random() * height as f64;
+        let color = path.li(&camera.get_ray_raster(x, y), scene);
+        let index = (y as u32).min(height - 1) * width + (x as u32).min(width - 1);
+        (color, index as usize)
+    };
+
+    let seed = |index: usize| sampler::sample_seed(camera.seed, index as u64, 0);
+
+    let weights = (0..bootstrap)
+        .into_par_iter()
+        .map(|index| {
+            let samples = PrimarySamples::new(seed(index), sigma, large_step_probability);
+            let (_, (color, _)) = sampler::with_primary_samples(samples, evaluate);
+            contribution(color)
+        })
+        .collect::<Vec<_>>();
+    let brightness = weights.iter().sum::<f64>() / bootstrap.max(1) as f64;
+    if brightness <= 0.0 || chains == 0 {
+        return vec![Color::black(); pixel_count];
+    }
+    let distribution = Distribution1D::new(weights);
+
+    let mutations = camera.sample_per_pixel as usize * pixel_count;
+    let run_chain = |image: &mut [Color], chain: usize| {
+        let mut rng = StdRng::seed_from_u64(seed(bootstrap + chain));
+        let (start, _) = distribution.sample_discrete(rng.gen());
+        let samples = PrimarySamples::new(seed(start), sigma, large_step_probability);
+        let (mut samples, (mut current, mut current_index)) =
+            sampler::with_primary_samples(samples, evaluate);
+        let chain_mutations = mutations / chains + usize::from(chain < mutations % chains);
+        for _ in 0..chain_mutations {
+            samples.start_iteration();
+            let (mutated, (proposed, proposed_index)) =
+                sampler::with_primary_samples(samples, evaluate);
+            samples = mutated;
+            let proposed_y = contribution(proposed);
+            let current_y = contribution(current);
+            let accept = (proposed_y / current_y).min(1.0);
+            if accept > 0.0 {
+                image[proposed_index] = image[proposed_index] + (accept / proposed_y) * proposed;
+            }
+            image[current_index] = image[current_index] + ((1.0 - accept) / current_y) * current;
+            if rng.gen::<f64>() < accept {
+                current = proposed;
+                current_index = proposed_index;
+                samples.accept();
+            } else {
+                samples.reject();
+            }
+        }
+    };
+
+    // chains are split into a fixed number of batches and the batches summed in order,
+    // so the image does not depend on how the work was scheduled.
+    let batches = chains.min(BATCHES);
+    let pb = ProgressBar::new(chains as u64);
+    let image = (0..batches)
+        .into_par_iter()
+        .map(|batch| {
+            let mut image = vec![Color::black(); pixel_count];
+            for chain in (batch..chains).step_by(batches) {
+                run_chain(&mut image, chain);
+                pb.inc(1);
+            }
+            image
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .fold(vec![Color::black(); pixel_count], |a, b| {
+            a.into_iter().zip(b).map(|(a, b)| a + b).collect()
+        });
+    pb.finish();
+
+    let scale = brightness * pixel_count as f64 / mutations.max(1) as f64;
+    image.into_iter().map(|color| scale * color).collect()
+}
+
+/// the scalar the chains sample in proportion to, zero for unusable radiance.
+fn contribution(color: Color) -> f64 {
+    let y = color.luminance();
+    if y.is_finite() && y > 0.0 {
+        y
+    } else {
+        0.0
+    }
+}
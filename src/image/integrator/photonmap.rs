@@ -0,0 +1,318 @@
+use crate::image::hittable::material::pdf::PDF;
+use crate::image::hittable::material::{BounceType, HitRecord, ScatterRecord};
+use crate::image::integrator::{Integrator, Scene};
+use crate::image::ray::Ray;
+use crate::image::sampler;
+use crate::image::util;
+use crate::image::vector::{Color, Vector};
+use crate::image::Camera;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+/// how much of each pass's photons a pixel keeps, shrinking its radius over time.
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// paths shorter than this are never terminated by russian roulette.
+const ROULETTE_DEPTH: u32 = 3;
+
+#[derive(Clone, Copy)]
+struct Photon {
+    p: Vector,
+    /// direction the photon arrived from.
+    wi: Vector,
+    power: Color,
+}
+
+/// photons in a balanced kd-tree, each subtree stored as a slice split at its median.
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u64>,
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        Self::build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [u64]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let mut min = Vector::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vector::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for photon in photons.iter() {
+            min = Vector::new(
+                min.x.min(photon.p.x),
+                min.y.min(photon.p.y),
+                min.z.min(photon.p.z),
+            );
+            max = Vector::new(
+                max.x.max(photon.p.x),
+                max.y.max(photon.p.y),
+                max.z.max(photon.p.z),
+            );
+        }
+        let extent = max - min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| a.p.axis(axis).total_cmp(&b.p.axis(axis)));
+        axes[mid] = axis;
+        let (left, right) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        Self::build(left, left_axes);
+        Self::build(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    /// calls `f` with every photon within `radius` of `p`.
+    fn for_each_near(&self, p: Vector, radius: f64, f: &mut impl FnMut(&Photon)) {
+        Self::search(&self.photons, &self.axes, p, radius, f);
+    }
+
+    fn search(
+        photons: &[Photon],
+        axes: &[u64],
+        p: Vector,
+        radius: f64,
+        f: &mut impl FnMut(&Photon),
+    ) {
+        if photons.is_empty() {
+            return;
+        }
+        let mid = photons.len() / 2;
+        let photon = &photons[mid];
+        if (photon.p - p).len_squared() <= radius * radius {
+            f(photon);
+        }
+        let delta = p.axis(axes[mid]) - photon.p.axis(axes[mid]);
+        if delta <= radius {
+            Self::search(&photons[..mid], &axes[..mid], p, radius, f);
+        }
+        if delta >= -radius {
+            Self::search(&photons[mid + 1..], &axes[mid + 1..], p, radius, f);
+        }
+    }
+}
+
+/// first diffuse surface seen through a pixel this pass.
+struct VisiblePoint {
+    rec: HitRecord,
+    beta: Color,
+    attenuation: Color,
+}
+
+/// running estimate of one pixel.
+struct PixelStats {
+    radius: f64,
+    count: f64,
+    /// flux gathered so far, scaled to the current radius.
+    tau: Color,
+    /// light that reached the pixel without photons, summed over passes.
+    direct: Color,
+}
+
+/// stochastic progressive photon mapping. every pass traces one camera path per pixel
+/// to its first diffuse hit, which gets direct light by light sampling, then gathers a
+/// fresh batch of photons shot from the light list. the gather radius shrinks as
+/// photons accumulate so the estimate converges. the environment and delta lights do
+/// not emit photons, and media only receive direct light.
+pub fn render(
+    camera: &Camera,
+    scene: &Scene,
+    width: u32,
+    height: u32,
+    photons_per_pass: usize,
+    radius: f64,
+    max_depth: u32,
+) -> Vec<Color> {
+    let mut pixels = (0..width * height)
+        .map(|_| PixelStats {
+            radius,
+            count: 0.0,
+            tau: Color::black(),
+            direct: Color::black(),
+        })
+        .collect::<Vec<_>>();
+    let passes = camera.sample_per_pixel;
+    let pb = ProgressBar::new(passes as u64);
+    for pass in 0..passes {
+        let visible_points = pixels
+            .par_iter_mut()
+            .enumerate()
+            .map(|(index, stats)| {
+                let (i, j) = (index as u32 / width, index as u32 % width);
+                camera.start_sample(index as u32, pass);
+                let ray = camera.get_ray(j, i);
+                let (direct, visible_point) = trace_camera(&ray, scene, max_depth);
+                stats.direct = stats.direct + direct;
+                visible_point
+            })
+            .collect::<Vec<_>>();
+
+        let photons = (0..photons_per_pass)
+            .into_par_iter()
+            .flat_map_iter(|photon| {
+                // photons take the sample indices after the last pixel.
+                let index = (width * height) as u64 + photon as u64;
+                sampler::seed_sample(camera.seed, index, pass as u64);
+                trace_photon(scene, max_depth)
+            })
+            .collect::<Vec<_>>();
+        let photon_map = PhotonMap::new(photons);
+
+        pixels
+            .par_iter_mut()
+            .zip(visible_points.par_iter())
+            .for_each(|(stats, visible_point)| {
+                if let Some(visible_point) = visible_point {
+                    gather(stats, visible_point, &photon_map);
+                }
+            });
+        pb.inc(1);
+    }
+    pb.finish();
+
+    let emitted = (passes as usize * photons_per_pass) as f64;
+    pixels
+        .iter()
+        .map(|stats| {
+            let indirect = stats.tau / (emitted * PI * stats.radius * stats.radius);
+            stats.direct / passes as f64 + indirect
+        })
+        .collect()
+}
+
+/// follows specular bounces to the first diffuse hit, returning the light found on the
+/// way and the point to gather photons at.
+fn trace_camera(ray: &Ray, scene: &Scene, max_depth: u32) -> (Color, Option<VisiblePoint>) {
+    let mut ray = *ray;
+    let mut beta = Color::white();
+    let mut radiance = Color::black();
+    for _ in 0..max_depth {
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(&ray, Integrator::interval(), &mut rec) {
+            return (
+                radiance + beta * scene.environment.value(ray.direction()),
+                None,
+            );
+        }
+        radiance = radiance + beta * rec.material.emitted(&ray, &rec);
+        let mut scatter_record = ScatterRecord::default();
+        if !rec.material.scatter(&ray, &rec, &mut scatter_record) {
+            break;
+        }
+        if scatter_record.skip_pdf {
+            beta = beta * scatter_record.attenuation;
+            ray = scatter_record.skip_pdf_ray;
+            continue;
+        }
+        radiance = radiance
+            + beta
+                * (Integrator::direct_light(&ray, &rec, &scatter_record, scene)
+                    + Integrator::bsdf_light_color(&ray, &rec, &scatter_record, scene));
+        if scatter_record.bounce != BounceType::Diffuse {
+            break;
+        }
+        let visible_point = VisiblePoint {
+            rec,
+            beta,
+            attenuation: scatter_record.attenuation,
+        };
+        return (radiance, Some(visible_point));
+    }
+    (radiance, None)
+}
+
+/// photons left at diffuse hits after the first bounce, the first is direct light.
+fn trace_photon(scene: &Scene, max_depth: u32) -> Vec<Photon> {
+    let mut photons = Vec::new();
+    if scene.lights.is_empty() {
+        return photons;
+    }
+    let (i, pmf) = scene.lights.sample_emitter();
+    let light = scene.lights.light(i);
+    let rec = light.sample_surface();
+    let emission = rec.material.emitted(&Ray::default(), &rec);
+    let pdf_origin = pmf / light.area();
+    let emission_pdf = PDF::new_cosine(rec.normal);
+    let direction = emission_pdf.generate();
+    let pdf_direction = emission_pdf.value(direction);
+    if pdf_origin <= 0.0 || pdf_direction <= 0.0 {
+        return photons;
+    }
+    let cosine = rec.normal.dot(direction.unit_vector()).abs();
+    let mut beta = (cosine / (pdf_origin * pdf_direction)) * emission;
+    let mut ray = Ray::new_time(rec.p, direction, util::random());
+
+    for depth in 0..max_depth {
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(&ray, Integrator::interval(), &mut rec) {
+            break;
+        }
+        let mut scatter_record = ScatterRecord::default();
+        if !rec.material.scatter(&ray, &rec, &mut scatter_record) {
+            break;
+        }
+        if depth > 0 && !scatter_record.skip_pdf && scatter_record.bounce == BounceType::Diffuse {
+            photons.push(Photon {
+                p: rec.p,
+                wi: -1.0 * ray.direction().unit_vector(),
+                power: beta,
+            });
+        }
+        let next_beta = if scatter_record.skip_pdf {
+            ray = scatter_record.skip_pdf_ray;
+            beta * scatter_record.attenuation
+        } else {
+            let scattered = Ray::new_time(rec.p, scatter_record.pdf.generate(), ray.time());
+            let pdf = scatter_record.pdf.value(scattered.direction());
+            let scattering_pdf = rec.material.scattering_pdf(&ray, &rec, &scattered);
+            if pdf <= 0.0 || scattering_pdf <= 0.0 {
+                break;
+            }
+            ray = scattered;
+            (scattering_pdf / pdf) * beta * scatter_record.attenuation
+        };
+        if depth + 1 >= ROULETTE_DEPTH {
+            let survival = (next_beta.max_component() / beta.max_component()).min(1.0);
+            if survival <= 0.0 || util::random() >= survival {
+                break;
+            }
+            beta = next_beta / survival;
+        } else {
+            beta = next_beta;
+        }
+    }
+    photons
+}
+
+/// adds this pass's photons around the visible point and shrinks the pixel's radius.
+fn gather(stats: &mut PixelStats, visible_point: &VisiblePoint, photon_map: &PhotonMap) {
+    let normal = visible_point.rec.normal;
+    let f = visible_point.attenuation / PI;
+    let mut phi = Color::black();
+    let mut found = 0.0;
+    photon_map.for_each_near(visible_point.rec.p, stats.radius, &mut |photon| {
+        if photon.wi.dot(normal) > 0.0 {
+            phi = phi + f * photon.power;
+            found += 1.0;
+        }
+    });
+    if found == 0.0 {
+        return;
+    }
+    let count = stats.count + ALPHA * found;
+    let radius = stats.radius * (count / (stats.count + found)).sqrt();
+    let shrink = (radius * radius) / (stats.radius * stats.radius);
+    stats.tau = shrink * (stats.tau + visible_point.beta * phi);
+    stats.count = count;
+    stats.radius = radius;
+}
@@ -0,0 +1,133 @@
+use crate::image::util;
+use crate::image::vector::{Color, Vector};
+
+#[derive(Clone)]
+enum LightType {
+    Point {
+        position: Vector,
+        intensity: Color,
+    },
+    Spot {
+        position: Vector,
+        direction: Vector,
+        intensity: Color,
+        cos_falloff_start: f64,
+        cos_total_width: f64,
+    },
+    Directional {
+        direction: Vector,
+        irradiance: Color,
+    },
+}
+
+/// delta light, it has no surface so rays never hit it and it is only reached by
+/// sampling it explicitly from a shading point.
+#[derive(Clone)]
+pub struct Light {
+    light: LightType,
+}
+
+/// a sampled connection from a shading point to a light.
+pub struct LightSample {
+    /// unit vector from the shading point towards the light.
+    pub direction: Vector,
+    /// distance to the light, infinite for directional lights.
+    pub distance: f64,
+    pub radiance: Color,
+}
+
+impl Light {
+    /// `intensity` is radiant intensity, falling off with the squared distance.
+    pub fn new_point(position: Vector, intensity: Color) -> Self {
+        Self {
+            light: LightType::Point {
+                position,
+                intensity,
+            },
+        }
+    }
+
+    /// point light restricted to a cone around `target`, with a smooth falloff between
+    /// `falloff_start` and `total_width` degrees off the axis.
+    pub fn new_spot(
+        position: Vector,
+        target: Vector,
+        intensity: Color,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            light: LightType::Spot {
+                position,
+                direction: (target - position).unit_vector(),
+                intensity,
+                cos_falloff_start: util::degree_to_radians(falloff_start.min(total_width)).cos(),
+                cos_total_width: util::degree_to_radians(total_width).cos(),
+            },
+        }
+    }
+
+    /// parallel light travelling along `direction`, like the sun.
+    pub fn new_directional(direction: Vector, irradiance: Color) -> Self {
+        Self {
+            light: LightType::Directional {
+                direction: direction.unit_vector(),
+                irradiance,
+            },
+        }
+    }
+
+    pub fn sample(&self, p: Vector) -> LightSample {
+        match self.light {
+            LightType::Point {
+                position,
+                intensity,
+            } => {
+                let to_light = position - p;
+                let distance = to_light.len();
+                LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: intensity / (distance * distance),
+                }
+            }
+            LightType::Spot {
+                position,
+                direction,
+                intensity,
+                cos_falloff_start,
+                cos_total_width,
+            } => {
+                let to_light = position - p;
+                let distance = to_light.len();
+                let wi = to_light / distance;
+                let falloff =
+                    Self::falloff(-wi.dot(direction), cos_falloff_start, cos_total_width);
+                LightSample {
+                    direction: wi,
+                    distance,
+                    radiance: (falloff / (distance * distance)) * intensity,
+                }
+            }
+            LightType::Directional {
+                direction,
+                irradiance,
+            } => LightSample {
+                direction: -direction,
+                distance: f64::INFINITY,
+                radiance: irradiance,
+            },
+        }
+    }
+
+    fn falloff(cos_theta: f64, cos_falloff_start: f64, cos_total_width: f64) -> f64 {
+        if cos_theta < cos_total_width {
+            return 0.0;
+        }
+        if cos_theta >= cos_falloff_start {
+            return 1.0;
+        }
+        let t = (cos_theta - cos_total_width) / (cos_falloff_start - cos_total_width);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
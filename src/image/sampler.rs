@@ -0,0 +1,288 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::f64::consts::PI;
+
+mod lowdiscrepancy;
+
+thread_local! {
+    /// stream feeding `util::random` on this thread, the thread rng when empty.
+    static STREAM: RefCell<Option<PrimarySamples>> = const { RefCell::new(None) };
+    /// rng behind `util::random` outside a stream, reseeded before each piece of work.
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::seed_from_u64(0));
+    /// pattern the current camera sample draws its dimensions from.
+    static PIXEL: RefCell<Option<PixelSample>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum SamplerType {
+    /// every dimension uniform and independent.
+    Independent,
+    /// jittered strata over each pair of dimensions, shuffled differently per pixel.
+    #[default]
+    Stratified,
+    /// owen scrambled halton, one prime base per dimension.
+    Halton,
+    /// owen scrambled sobol (0, 2) sequence for each pair of dimensions, the samples
+    /// shuffled differently per pair. best with power of two sample counts.
+    Sobol,
+}
+
+/// how the samples of a pixel spread over every dimension of the paths they start.
+/// dimensions are handed out in the order `util::random` is called, the pixel position
+/// first, then the lens and time, then the light and bsdf samples of each bounce.
+#[derive(Clone, Copy, Default)]
+pub struct Sampler {
+    sampler: SamplerType,
+}
+
+impl Sampler {
+    pub fn new_independent() -> Self {
+        Self {
+            sampler: SamplerType::Independent,
+        }
+    }
+
+    pub fn new_stratified() -> Self {
+        Self {
+            sampler: SamplerType::Stratified,
+        }
+    }
+
+    pub fn new_halton() -> Self {
+        Self {
+            sampler: SamplerType::Halton,
+        }
+    }
+
+    pub fn new_sobol() -> Self {
+        Self {
+            sampler: SamplerType::Sobol,
+        }
+    }
+
+    /// identifies the pattern in checkpoints.
+    pub fn fingerprint(&self) -> u64 {
+        self.sampler as u64
+    }
+
+    /// installs sample `sample` of `samples` in `pixel` on this thread and seeds the rng
+    /// for whatever the pattern does not cover.
+    pub fn start_sample(&self, seed: u64, pixel: u64, sample: u32, samples: u32) {
+        seed_sample(seed, pixel, sample as u64);
+        let pixel_sample = PixelSample {
+            sampler: self.sampler,
+            hash: pixel_seed(seed, pixel),
+            index: sample,
+            samples: samples.max(1),
+            dimension: 0,
+        };
+        PIXEL.with(|pixel| *pixel.borrow_mut() = Some(pixel_sample));
+    }
+}
+
+/// position in the pattern of the camera sample being traced.
+struct PixelSample {
+    sampler: SamplerType,
+    hash: u64,
+    index: u32,
+    samples: u32,
+    dimension: u32,
+}
+
+impl PixelSample {
+    /// the next dimension, `None` when it is left to the rng.
+    fn next(&mut self) -> Option<f64> {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match self.sampler {
+            SamplerType::Independent => None,
+            SamplerType::Stratified => self.next_stratified(dimension),
+            SamplerType::Halton => {
+                lowdiscrepancy::halton(dimension, self.index, mix(self.hash ^ dimension as u64))
+            }
+            SamplerType::Sobol => Some(self.next_sobol(dimension)),
+        }
+    }
+
+    fn next_stratified(&self, dimension: u32) -> Option<f64> {
+        let x_strata = ((self.samples as f64).sqrt() as u32).max(1);
+        let y_strata = self.samples.div_ceil(x_strata);
+        let strata = x_strata * y_strata;
+        if self.index >= strata {
+            return None;
+        }
+        let pair_hash = mix(self.hash ^ (dimension / 2) as u64) as u32;
+        let stratum = lowdiscrepancy::permutation_element(self.index, strata, pair_hash);
+        let jitter = uniform();
+        if dimension.is_multiple_of(2) {
+            Some(((stratum % x_strata) as f64 + jitter) / x_strata as f64)
+        } else {
+            Some(((stratum / x_strata) as f64 + jitter) / y_strata as f64)
+        }
+    }
+
+    fn next_sobol(&self, dimension: u32) -> f64 {
+        let pair_hash = mix(self.hash ^ (dimension / 2) as u64);
+        let length = self.samples.next_power_of_two();
+        let index = if self.index < length {
+            lowdiscrepancy::permutation_element(self.index, length, pair_hash as u32)
+        } else {
+            self.index
+        };
+        let scramble = mix(pair_hash ^ (dimension % 2) as u64) as u32;
+        lowdiscrepancy::sobol(index, dimension % 2, scramble)
+    }
+}
+
+/// uniform in [0, 1) from this thread's rng.
+pub fn uniform() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// a fresh seed from this thread's rng, for noise textures built along with a scene.
+pub fn next_seed() -> u64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// restarts this thread's rng from `seed`, dropping any sample pattern.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+    PIXEL.with(|pixel| *pixel.borrow_mut() = None);
+}
+
+/// restarts this thread's rng for one sample of one pixel, so what the sample draws
+/// depends on where it is and not on the thread tracing it.
+pub fn seed_sample(seed: u64, pixel: u64, sample: u64) {
+    self::seed(sample_seed(seed, pixel, sample));
+}
+
+/// seed of one sample of one pixel, mixed from the image seed.
+pub fn sample_seed(seed: u64, pixel: u64, sample: u64) -> u64 {
+    mix(pixel_seed(seed, pixel) ^ sample)
+}
+
+/// seed shared by every sample of one pixel.
+fn pixel_seed(seed: u64, pixel: u64) -> u64 {
+    mix(mix(seed) ^ pixel)
+}
+
+/// splitmix64 finalizer.
+pub fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// the next number from the installed stream or sample pattern, if there is one.
+pub fn next() -> Option<f64> {
+    STREAM
+        .with(|stream| stream.borrow_mut().as_mut().map(|samples| samples.next()))
+        .or_else(|| PIXEL.with(|pixel| pixel.borrow_mut().as_mut().and_then(PixelSample::next)))
+}
+
+/// runs `f` with `samples` feeding `util::random` on this thread, handing them back
+/// afterwards.
+pub fn with_primary_samples<R>(
+    samples: PrimarySamples,
+    f: impl FnOnce() -> R,
+) -> (PrimarySamples, R) {
+    STREAM.with(|stream| *stream.borrow_mut() = Some(samples));
+    let result = f();
+    let samples = STREAM.with(|stream| stream.borrow_mut().take());
+    (samples.expect("sample stream removed while in use"), result)
+}
+
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    last_modification: u64,
+    value_backup: f64,
+    modification_backup: u64,
+}
+
+/// the random numbers one path consumes, as a point in primary sample space that
+/// metropolis mutates. values are mutated lazily, when a path asks for them.
+pub struct PrimarySamples {
+    rng: StdRng,
+    samples: Vec<PrimarySample>,
+    sigma: f64,
+    large_step_probability: f64,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    index: usize,
+}
+
+impl PrimarySamples {
+    /// starts at a uniformly random point, the same one for the same seed.
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            sigma,
+            large_step_probability,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    /// proposes a mutation, either a fresh uniform point or a small step from this one.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modification == self.iteration {
+                sample.value = sample.value_backup;
+                sample.last_modification = sample.modification_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn next(&mut self) -> f64 {
+        let index = self.index;
+        self.index += 1;
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+        let uniform = self.rng.gen::<f64>();
+        let normal = self.normal();
+        let sample = &mut self.samples[index];
+        // catch up on the large step this sample missed while unused.
+        if sample.last_modification < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.last_modification = self.last_large_step;
+        }
+        sample.value_backup = sample.value;
+        sample.modification_backup = sample.last_modification;
+        if self.large_step {
+            sample.value = uniform;
+        } else {
+            let steps = (self.iteration - sample.last_modification) as f64;
+            sample.value += normal * self.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modification = self.iteration;
+        sample.value
+    }
+
+    fn normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.rng.gen::<f64>();
+        let u2 = self.rng.gen::<f64>();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
@@ -0,0 +1,97 @@
+/// largest f64 below one.
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// one prime base per halton dimension, later dimensions fall back to the rng.
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// element `i` of a random permutation of `0..l` chosen by `p`, after Kensler.
+pub fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+/// radical inverse of `index` in the prime base of `dimension` with every digit
+/// permuted depending on the digits before it, `None` past the last base.
+pub fn halton(dimension: u32, index: u32, hash: u64) -> Option<f64> {
+    let base = *PRIMES.get(dimension as usize)?;
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut value = 0.0;
+    let mut digit_hash = hash;
+    let mut a = index as u64;
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+        let next = a / base;
+        let digit = (a - next * base) as u32;
+        let digit = permutation_element(digit, base as u32, digit_hash as u32);
+        inv_base_m *= inv_base;
+        value += digit as f64 * inv_base_m;
+        digit_hash = super::mix(digit_hash ^ digit as u64);
+        a = next;
+    }
+    Some(value.min(ONE_MINUS_EPSILON))
+}
+
+/// one of the two dimensions of the sobol (0, 2) sequence, owen scrambled by `seed`.
+pub fn sobol(index: u32, dimension: u32, seed: u32) -> f64 {
+    let v = if dimension == 0 {
+        index.reverse_bits()
+    } else {
+        let mut v = 0;
+        let mut column = 1 << 31;
+        let mut i = index;
+        while i != 0 {
+            if i & 1 != 0 {
+                v ^= column;
+            }
+            i >>= 1;
+            column ^= column >> 1;
+        }
+        v
+    };
+    (owen_scramble(v, seed) as f64 / 4_294_967_296.0).min(ONE_MINUS_EPSILON)
+}
+
+/// nested uniform scrambling of the bits of `v`, each flipped depending on the bits
+/// above it, after Laine and Karras.
+fn owen_scramble(mut v: u32, seed: u32) -> u32 {
+    v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
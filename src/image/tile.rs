@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+/// order tiles are handed to the render threads in.
+#[derive(Clone, Copy, Default)]
+pub enum TileOrder {
+    /// outward from the centre tile, where the subject usually is.
+    #[default]
+    Spiral,
+    /// along a hilbert curve over the tile grid, so consecutive tiles touch and share
+    /// what they pull into the cache.
+    Hilbert,
+}
+
+/// splits the image into `size` by `size` tiles rendered in `order`, saving a preview
+/// with the tiles finished so far at most once per `preview_interval`.
+#[derive(Clone, Copy)]
+pub struct Tiles {
+    pub size: u32,
+    pub order: TileOrder,
+    pub preview_interval: Option<Duration>,
+}
+
+impl Tiles {
+    pub fn new(size: u32) -> Self {
+        Self {
+            size,
+            order: TileOrder::default(),
+            preview_interval: None,
+        }
+    }
+
+    /// the tiles covering a `width` by `height` image, in render order. tiles on the
+    /// right and bottom edges are cut to fit.
+    pub fn split(&self, width: u32, height: u32) -> Vec<Tile> {
+        let size = self.size.max(1);
+        let (columns, rows) = (width.div_ceil(size), height.div_ceil(size));
+        let cells = match self.order {
+            TileOrder::Spiral => spiral(columns, rows),
+            TileOrder::Hilbert => hilbert(columns, rows),
+        };
+        cells
+            .into_iter()
+            .map(|(column, row)| Tile {
+                x0: column * size,
+                y0: row * size,
+                x1: ((column + 1) * size).min(width),
+                y1: ((row + 1) * size).min(height),
+            })
+            .collect()
+    }
+}
+
+/// a rectangle of pixels, `x1` and `y1` exclusive.
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    /// image coordinates of every pixel, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y0..self.y1).flat_map(move |y| (self.x0..self.x1).map(move |x| (x, y)))
+    }
+}
+
+/// grid cells walked in a square spiral from the centre, skipping those outside.
+fn spiral(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let total = (columns * rows) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut x, mut y) = ((columns as i64 - 1) / 2, (rows as i64 - 1) / 2);
+    let (mut dx, mut dy) = (1, 0);
+    let mut leg = 1;
+    while cells.len() < total {
+        // legs come in pairs of equal length, 1, 1, 2, 2, 3, 3 and so on.
+        for _ in 0..2 {
+            for _ in 0..leg {
+                if (0..columns as i64).contains(&x) && (0..rows as i64).contains(&y) {
+                    cells.push((x as u32, y as u32));
+                }
+                x += dx;
+                y += dy;
+            }
+            (dx, dy) = (-dy, dx);
+        }
+        leg += 1;
+    }
+    cells
+}
+
+/// grid cells sorted by their distance along a hilbert curve over the smallest power
+/// of two square holding the grid.
+fn hilbert(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let n = columns.max(rows).next_power_of_two();
+    let mut cells = (0..rows)
+        .flat_map(|y| (0..columns).map(move |x| (x, y)))
+        .collect::<Vec<_>>();
+    cells.sort_by_key(|&(x, y)| hilbert_index(n, x, y));
+    cells
+}
+
+/// distance of `(x, y)` along the hilbert curve filling an `n` by `n` grid.
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // rotate the quadrant so the curve inside it starts and ends in the right place.
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
@@ -1,4 +1,4 @@
-use rand::Rng;
+use crate::image::sampler;
 use std::f64::consts::PI;
 use std::ops::Add;
 
@@ -7,12 +7,24 @@ pub fn degree_to_radians(degree: f64) -> f64 {
     (degree * PI) / 180.0
 }
 
+/// uniform in [0, 1), from the thread's sample stream when one is installed and the
+/// thread's seeded rng otherwise.
 pub fn random() -> f64 {
-    rand::thread_rng().gen_range(0.0..1.0)
+    sampler::next().unwrap_or_else(sampler::uniform)
 }
 
 pub fn random_interval(min: f64, max: f64) -> f64 {
-    rand::thread_rng().gen_range(min..max)
+    min + (max - min) * random()
+}
+
+/// multiple importance sampling weight for strategy `f` against `g`, one sample each.
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g <= 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
 }
 
 #[derive(Default, Copy, Clone)]
@@ -88,3 +100,158 @@ impl Add<f64> for Interval {
         }
     }
 }
+
+/// piecewise constant distribution over [0, 1) built from non-negative weights.
+#[derive(Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution1D {
+    /// all zero weights fall back to a uniform distribution.
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut func = func;
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1] / n as f64;
+        }
+        let mut func_int = cdf[n];
+        if func_int <= 0.0 {
+            func = vec![1.0; n];
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f64 / n as f64;
+            }
+            func_int = 1.0;
+        } else {
+            for c in cdf.iter_mut() {
+                *c /= func_int;
+            }
+        }
+        Self {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.func_int
+    }
+
+    pub fn func(&self, i: usize) -> f64 {
+        self.func[i]
+    }
+
+    fn find_interval(&self, u: f64) -> usize {
+        let i = self.cdf.partition_point(|c| *c <= u);
+        i.saturating_sub(1).min(self.count() - 1)
+    }
+
+    /// returns the sampled point in [0, 1), its density and the segment index.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let i = self.find_interval(u);
+        let mut du = u - self.cdf[i];
+        let width = self.cdf[i + 1] - self.cdf[i];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = self.func[i] / self.func_int;
+        ((i as f64 + du) / self.count() as f64, pdf, i)
+    }
+
+    /// returns the sampled index and its probability.
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let i = self.find_interval(u);
+        (i, self.discrete_pdf(i))
+    }
+
+    pub fn discrete_pdf(&self, i: usize) -> f64 {
+        self.func[i] / (self.func_int * self.count() as f64)
+    }
+}
+
+/// piecewise constant distribution over the unit square, rows are sampled first.
+#[derive(Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` is row major with `width` entries per row.
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional = (0..height)
+            .map(|y| Distribution1D::new(func[y * width..(y + 1) * width].to_vec()))
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// returns (x, y) in the unit square and the density there.
+    pub fn sample_continuous(&self, u0: f64, u1: f64) -> (f64, f64, f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u1);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u0);
+        (x, y, pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let width = self.conditional[0].count();
+        let height = self.marginal.count();
+        let ix = ((x * width as f64) as usize).min(width - 1);
+        let iy = ((y * height as f64) as usize).min(height - 1);
+        self.conditional[iy].func(ix) / self.marginal.integral()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_1d_integrates_to_one() {
+        for func in [vec![1.0, 0.0, 3.0, 0.5, 2.0], vec![0.0; 4]] {
+            let distribution = Distribution1D::new(func);
+            let n = distribution.count();
+            let density = |i| distribution.func(i) / distribution.integral();
+            let continuous = (0..n).map(|i| density(i) / n as f64).sum::<f64>();
+            let discrete = (0..n).map(|i| distribution.discrete_pdf(i)).sum::<f64>();
+            assert!((continuous - 1.0).abs() < 1e-9, "{}", continuous);
+            assert!((discrete - 1.0).abs() < 1e-9, "{}", discrete);
+            for k in 0..100 {
+                let (x, pdf, i) = distribution.sample_continuous((k as f64 + 0.5) / 100.0);
+                assert_eq!(i, (x * n as f64) as usize);
+                assert!((pdf - density(i)).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn distribution_2d_integrates_to_one() {
+        let (width, height) = (7, 5);
+        let func = (0..width * height)
+            .map(|i| ((i * 37) % 11) as f64)
+            .collect::<Vec<_>>();
+        let distribution = Distribution2D::new(&func, width, height);
+        let n = 280;
+        let mut integral = 0.0;
+        for j in 0..n {
+            for i in 0..n {
+                let (x, y) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                integral += distribution.pdf(x, y) / (n * n) as f64;
+                let (sx, sy, pdf) = distribution.sample_continuous(x, y);
+                assert!((pdf - distribution.pdf(sx, sy)).abs() < 1e-9);
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-9, "{}", integral);
+    }
+}
@@ -60,13 +60,23 @@ impl Vector {
         )
     }
 
+    /// cosine weighted direction on the +z hemisphere.
     pub fn random_unit_vector() -> Self {
         let theta = 2.0 * std::f64::consts::PI * random();
-        let z = random();
-        let r = (1.0 - z).sqrt();
+        let r2 = random();
+        let z = (1.0 - r2).sqrt();
+        let r = r2.sqrt();
         Self::new(r * f64::cos(theta), r * f64::sin(theta), z)
     }
 
+    /// uniformly distributed over the whole sphere.
+    pub fn random_on_unit_sphere() -> Self {
+        let z = 1.0 - 2.0 * random();
+        let r = (1.0 - z * z).sqrt();
+        let phi = 2.0 * PI * random();
+        Self::new(r * f64::cos(phi), r * f64::sin(phi), z)
+    }
+
     pub fn random_on_hemisphere(normal: Self) -> Self {
         let on_unit_sphere = Vector::random_unit_vector();
         if on_unit_sphere.dot(normal) > 0.0 {
@@ -76,9 +86,11 @@ impl Vector {
         }
     }
 
+    /// uniformly distributed over the unit disk.
     pub fn random_in_unit_disk() -> Self {
+        let r = random().sqrt();
         let theta = 2.0 * std::f64::consts::PI * random();
-        Self::new(f64::cos(theta), f64::sin(theta), 0.0)
+        Self::new(r * f64::cos(theta), r * f64::sin(theta), 0.0)
     }
 
     pub fn random_to_sphere(radius: f64, dist_sqrd: f64) -> Self {
@@ -247,6 +259,26 @@ impl Color {
         Self::new(0.0, 1.0, 1.0)
     }
 
+    pub fn magenta() -> Self {
+        Self::new(1.0, 0.0, 1.0)
+    }
+
+    pub fn clamp(&self, min: f64, max: f64) -> Self {
+        Self::new(
+            self.r.clamp(min, max),
+            self.g.clamp(min, max),
+            self.b.clamp(min, max),
+        )
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
     fn linear_to_gamma(linear: f64) -> f64 {
         if linear > 0.0 {
             return linear.sqrt();
@@ -321,3 +353,35 @@ impl Div<f64> for Color {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_unit_vector_is_cosine_weighted() {
+        let n = 100_000;
+        let mut z_sum = 0.0;
+        for _ in 0..n {
+            let v = Vector::random_unit_vector();
+            assert!((v.len() - 1.0).abs() < 1e-9);
+            assert!(v.z >= 0.0);
+            z_sum += v.z;
+        }
+        // the mean cosine under a cos / pi density is 2 / 3.
+        assert!((z_sum / n as f64 - 2.0 / 3.0).abs() < 0.005);
+    }
+
+    #[test]
+    fn random_in_unit_disk_covers_the_disk() {
+        let n = 100_000;
+        let mut r2_sum = 0.0;
+        for _ in 0..n {
+            let p = Vector::random_in_unit_disk();
+            assert!(p.len_squared() <= 1.0 && p.z == 0.0);
+            r2_sum += p.len_squared();
+        }
+        // the mean squared radius over a uniform disk is 1 / 2, the edge gives 1.
+        assert!((r2_sum / n as f64 - 0.5).abs() < 0.005);
+    }
+}
@@ -1,7 +1,82 @@
 #[allow(dead_code)]
 mod image;
+use image::environment::Environment;
+use image::integrator::{BounceLimits, Integrator};
 use image::Image;
+
+const MAX_DEPTH: u32 = 50;
+const USAGE: &str = "usage: ray-tracer [--scene 1-9] [--integrator NAME] [--environment sky|FILE]
+    [--diffuse-bounces N] [--glossy-bounces N] [--transmission-bounces N] [--volume-bounces N]
+    [--roulette-depth N]
+integrators: path, bdpt, sppm, mlt, direct, ao, normals, albedo";
+
 fn main() {
-    let mut image = Image::new(1.0, 800, 500, 50);
-    image.render_par();
+    let mut image = Image::new(1.0, 800, 500, MAX_DEPTH);
+    if let Err(error) = configure(&mut image, std::env::args().skip(1)) {
+        eprintln!("{}\n{}", error, USAGE);
+        std::process::exit(2);
+    }
+    if let Err(error) = image.render_par() {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+/// applies the `--option value` pairs given on the command line. the bounce limits
+/// apply to the path and metropolis integrators.
+fn configure(image: &mut Image, mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut name = String::from("path");
+    let mut limits = BounceLimits::new(MAX_DEPTH);
+    while let Some(option) = args.next() {
+        let Some(value) = args.next() else {
+            return Err(format!("{} needs a value", option));
+        };
+        match option.as_str() {
+            "--scene" => match value.parse() {
+                Ok(case) if (1..=9).contains(&case) => image.set_scene(case),
+                _ => return Err(format!("no scene {}", value)),
+            },
+            "--integrator" => name = value,
+            "--environment" => image.set_environment(environment(value)?),
+            "--diffuse-bounces" => limits.diffuse = count(&option, &value)?,
+            "--glossy-bounces" => limits.glossy = count(&option, &value)?,
+            "--transmission-bounces" => limits.transmission = count(&option, &value)?,
+            "--volume-bounces" => limits.volume = count(&option, &value)?,
+            "--roulette-depth" => limits.roulette_depth = count(&option, &value)?,
+            _ => return Err(format!("unknown option {}", option)),
+        }
+    }
+    image.set_integrator(integrator(&name, limits)?);
+    Ok(())
+}
+
+fn count(option: &str, value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} takes a count, not {}", option, value))
+}
+
+fn integrator(name: &str, limits: BounceLimits) -> Result<Integrator, String> {
+    match name {
+        "path" => Ok(Integrator::new_path(limits)),
+        "bdpt" => Ok(Integrator::new_bidirectional(MAX_DEPTH)),
+        "sppm" => Ok(Integrator::new_photon_mapping(100_000, 20.0, MAX_DEPTH)),
+        "mlt" => Ok(Integrator::new_metropolis(
+            limits, 1_000_000, 1024, 0.01, 0.3,
+        )),
+        "direct" => Ok(Integrator::new_direct_lighting(MAX_DEPTH)),
+        "ao" => Ok(Integrator::new_ambient_occlusion(100.0)),
+        "normals" => Ok(Integrator::new_normals()),
+        "albedo" => Ok(Integrator::new_albedo()),
+        _ => Err(format!("unknown integrator {}", name)),
+    }
+}
+
+/// a clear afternoon sky with the sun behind the camera, or an equirectangular image
+/// lighting the scene from all around.
+fn environment(name: String) -> Result<Environment, String> {
+    match name.as_str() {
+        "sky" => Ok(Environment::new_sky(35.0, 200.0, 3.0, 0.05)),
+        _ => Environment::new_map(name, 0.0, 1.0).map_err(|error| error.to_string()),
+    }
 }